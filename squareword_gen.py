@@ -7,27 +7,170 @@ Nathan Parker
 """
 
 import argparse
+import collections
+import copy
 import csv
+import gzip
+import hashlib
+import heapq
+import importlib.util
+import json
+import math
+import multiprocessing
+import os
+import pickle
 import random
+import subprocess
+import sys
+import tempfile
 import time
+import traceback
+import unicodedata
 
 
 LOG_DETAILS = False
+# collections.Counter of what the row-by-row search does, for --stats. None
+# when it isn't wanted, so the search doesn't pay for counting.
+SEARCH_STATS = None
 
 
 class Logger:
     """A simple logger class that lets you set a prefix string.
+
+      Like the logging module, log() only formats msg % args if it's going
+      to print it, since the search logs every step and formatting all of
+      that (to then throw it away) was a good part of its time.
     """
 
     def __init__(self, prefix_str):
+        """
+          Args:
+            prefix_str: string to start each line with, or a list of strings
+              to join with "/" for it, which is only done if a line gets
+              printed
+        """
         self._prefix_str = prefix_str
 
-    def log(self, msg):
+    def log(self, msg, *args):
         if LOG_DETAILS:
-            print(self._prefix_str + " " + msg)
+            prefix_str = self._prefix_str
+            if not isinstance(prefix_str, str):
+                prefix_str = "/".join(prefix_str)
+            print(prefix_str + " " + (msg % args if args else msg))
+
+
+class NormalizeOptions:
+    """Settings for how NormalizeWord canonicalizes words.
+
+      Attributes:
+        alphabet: set of allowed chars, or None to allow anything. Words with
+          other chars are dropped.
+        strip_accents: if True, turn accented chars into their plain form
+          (e.g. "café" -> "cafe") before checking the alphabet.
+    """
+
+    def __init__(self, alphabet=None, strip_accents=False):
+        self.alphabet = set(alphabet) if alphabet else None
+        self.strip_accents = strip_accents
+
+
+def NormalizeWord(word, opts):
+    """Canonicalize a word from any input file.
+
+      Every place that reads words should go through this, so the same word
+      always ends up spelled the same way.
+
+      Args:
+        word: raw string, as read from a file
+        opts: NormalizeOptions
+      Returns:
+        The trimmed, lowercased word, or None if it has chars outside
+        opts.alphabet.
+    """
+
+    word = word.strip().lower()
+    if opts.strip_accents:
+        word = "".join([c for c in unicodedata.normalize('NFD', word)
+                        if not unicodedata.combining(c)])
+    if opts.alphabet is not None and not set(word) <= opts.alphabet:
+        return None
+    return word
+
+
+INPUT_FORMATS = ["text", "json", "gzip-text", "csv"]
+
+# Offensive words for --family_friendly to leave out, kept next to this file.
+DENY_WORDS_FILE = os.path.join(os.path.dirname(os.path.abspath(__file__)),
+                               "deny_words.txt")
+
+
+def GuessInputFormat(filename):
+    """Guess which of INPUT_FORMATS a word file is in, from its extension.
+    """
+
+    if filename.endswith(".gz"):
+        return "gzip-text"
+    if filename.endswith(".json"):
+        return "json"
+    if filename.endswith(".csv"):
+        return "csv"
+    return "text"
+
+
+class WordFileError(Exception):
+    """A word file couldn't be opened, or isn't in the format it was read as.
+    """
+
+
+def ReadWordFile(filename, input_format=None):
+    """Return a generator of the raw words in a word file, in file order.
+
+      Args:
+        filename: file to read, or "-" for stdin
+        input_format: one of INPUT_FORMATS, or None to guess from filename:
+          text: one word per line
+          json: a list of words, or of lists whose first item is the word
+          gzip-text: gzipped text
+          csv: the word is the first column
+      Raises WordFileError if the file can't be read that way.
+    """
 
+    if input_format is None:
+        input_format = GuessInputFormat(filename)
 
-def GetWorkingWords(freq_csv_file, scrabble_words_file, top_n, word_len):
+    try:
+        if input_format == "gzip-text":
+            f = gzip.open(sys.stdin.buffer if filename == "-" else filename,
+                          'rt')
+        elif filename == "-":
+            f = sys.stdin
+        else:
+            f = open(filename, 'r', newline='')
+
+        with f:
+            if input_format == "json":
+                for entry in json.load(f):
+                    yield entry if isinstance(entry, str) else entry[0]
+            elif input_format == "csv":
+                for row in csv.reader(f, delimiter=','):
+                    if row:
+                        yield row[0]
+            else:
+                for line in f:
+                    yield line
+    except (OSError, EOFError, ValueError, LookupError, TypeError,
+            csv.Error) as e:
+        raise WordFileError("Can't read %s as %s: %s" %
+                            (filename, input_format, e))
+
+
+def GetWorkingWords(freq_csv_file, scrabble_words_file, top_n, word_len,
+                    normalize_opts=NormalizeOptions(), input_format=None,
+                    exclude_words=frozenset(), ban_letters="", min_vowels=0,
+                    max_vowels=None, no_plurals=False, lemmas=None,
+                    isograms_only=False, exclude_substrings=(),
+                    proper_nouns=frozenset(), per_letter=False,
+                    freq_format=None):
     """Read in the two files, return the top N from the scrabble file
 
     Args:
@@ -35,29 +178,87 @@ def GetWorkingWords(freq_csv_file, scrabble_words_file, top_n, word_len):
       scrabble_words_file: filename of flat text file of scrabble words
       top_n: integer of how many words to return
       word_len: integer of word length to pick out
+      normalize_opts: NormalizeOptions applied to words from both files
+      input_format: Optional one of INPUT_FORMATS to read the scrabble file
+        as, rather than guessing from its name
+      exclude_words: set of normalized words to drop from the scrabble words
+      ban_letters: string of letters. Words with any of them are dropped.
+      min_vowels, max_vowels: Optional limits on how many of a word's letters
+        can be VOWELS
+      no_plurals: if True, drop the words IsInflection says are just
+        another word plus an ending
+      lemmas: Optional dict of word -> lemma, from ReadLemmas. Words whose
+        lemma is also a scrabble word are dropped.
+      isograms_only: if True, drop words that use any letter twice
+      exclude_substrings: list of strings. Words with any of them in them
+        are dropped.
+      proper_nouns: set of normalized words to allow as well as the
+        scrabble words. The other filters apply to them too.
+      per_letter: if True, take the top top_n / 26 words for each first
+        letter, rather than the top_n overall
+      freq_format: as for input_format, but for the frequency file
     """
 
-    scrabble_set = set()
-    with open(scrabble_words_file, 'r') as f:
-        for line in f:
-            scrabble_set.add(line.lower().strip())
+    scrabble_set = ReadScrabbleSet(scrabble_words_file, normalize_opts,
+                                   input_format)
     print("Found %d scrabble words" % (len(scrabble_set)))
+    if proper_nouns:
+        num_words = len(scrabble_set)
+        scrabble_set |= proper_nouns
+        print("Added %d proper nouns" % (len(scrabble_set) - num_words))
+    if exclude_words:
+        num_words = len(scrabble_set)
+        scrabble_set -= exclude_words
+        print("Excluded %d of them" % (num_words - len(scrabble_set)))
+    if ban_letters:
+        num_words = len(scrabble_set)
+        scrabble_set = WithoutLetters(scrabble_set, ban_letters)
+        print("Removed %d words with the banned letters \"%s\"" %
+              (num_words - len(scrabble_set), ban_letters))
+    if min_vowels or max_vowels is not None:
+        num_words = len(scrabble_set)
+        if max_vowels is None:
+            max_vowels = max([len(word) for word in scrabble_set], default=0)
+        scrabble_set = set([word for word in scrabble_set
+                            if min_vowels <= CountVowels(word) <= max_vowels])
+        print("Removed %d words with too few or too many vowels" %
+              (num_words - len(scrabble_set)))
+    if no_plurals:
+        num_words = len(scrabble_set)
+        # Check the stems against the full set, not the shrinking one.
+        scrabble_set = set([word for word in scrabble_set
+                            if not IsInflection(word, scrabble_set)])
+        print("Removed %d plurals and -ed words" %
+              (num_words - len(scrabble_set)))
+    if lemmas:
+        num_words = len(scrabble_set)
+        # As for no_plurals, look the lemmas up in the full set.
+        scrabble_set = set([word for word in scrabble_set
+                            if lemmas.get(word) not in scrabble_set])
+        print("Removed %d words whose lemma is also a word" %
+              (num_words - len(scrabble_set)))
+    if isograms_only:
+        num_words = len(scrabble_set)
+        scrabble_set = set([word for word in scrabble_set
+                            if len(set(word)) == len(word)])
+        print("Removed %d words with a repeated letter" %
+              (num_words - len(scrabble_set)))
+    if exclude_substrings:
+        num_words = len(scrabble_set)
+        scrabble_set = set([word for word in scrabble_set
+                            if not any([substring in word
+                                        for substring in exclude_substrings])])
+        print("Removed %d words containing %s" %
+              (num_words - len(scrabble_set),
+               ", ".join(['"%s"' % substring
+                          for substring in exclude_substrings])))
 
-    working_words = []
-    with open(freq_csv_file, newline='') as csvfile:
-        for row in csv.reader(csvfile, delimiter=','):
-            # Assume it's sorted by frequency, so we just count the top N
-            # that are scrabble words
-            word = row[0].lower().strip()
-            if (len(word) != word_len):
-                continue
-            if word in scrabble_set:
-                working_words.append(word)
-                if len(working_words) >= top_n:
-                    break
+    working_words = PickTopWords(freq_csv_file, scrabble_set, top_n, word_len,
+                                 normalize_opts, freq_format, per_letter)
 
-    print("Picked the top %d scrabble words. Here's some of the top/bottom ones:" %
-          (len(working_words)))
+    print("Picked the top %d scrabble words, using the alphabet \"%s\". "
+          "Here's some of the top/bottom ones:" %
+          (len(working_words), "".join(WordAlphabet(working_words))))
 
     for word in working_words[0:10]:
         print("  " + word)
@@ -70,12 +271,155 @@ def GetWorkingWords(freq_csv_file, scrabble_words_file, top_n, word_len):
     return working_words
 
 
+VOWELS = "aeiou"
+
+
+def CountVowels(word):
+    """Return how many of word's letters are in VOWELS."""
+
+    return len([c for c in word if c in VOWELS])
+
+
+def IsInflection(word, words):
+    """Guess whether word is a plural or past tense of another of words.
+
+      "cats" (cat), "boxes" (box), "baked" (bake) and "wanted" (want) are,
+      but "boss" and "bed" aren't. This is just a heuristic on the endings,
+      so it has misses both ways ("news" goes, since "new" is a word).
+    """
+
+    # Stems have to be 3+ letters, or "bed" would be "be" + "d".
+    stems = []
+    if word.endswith("s") and not word.endswith("ss"):
+        stems.append(word[:-1])
+    if word.endswith("es") or word.endswith("ed"):
+        stems.append(word[:-2])
+    if word.endswith("ed"):
+        stems.append(word[:-1])
+    return any([len(stem) >= 3 and stem in words for stem in stems])
+
+
+def ReadLemmas(filename, normalize_opts=NormalizeOptions()):
+    """Read a lemma file, and return a dict of word -> lemma.
+
+      Each line is a lemma followed by the forms made from it, separated by
+      spaces, like "run running runner runs". Lines starting with "#" are
+      comments. A form listed under two lemmas keeps the first.
+    """
+
+    lemmas = {}
+    try:
+        with open(filename) as f:
+            for line in f:
+                if line.startswith("#"):
+                    continue
+                words = [NormalizeWord(word, normalize_opts)
+                         for word in line.split()]
+                if not words or words[0] is None:
+                    continue
+                for word in words[1:]:
+                    if word is not None and word != words[0]:
+                        lemmas.setdefault(word, words[0])
+    except (OSError, ValueError) as e:
+        raise WordFileError("Can't read %s: %s" % (filename, e))
+    return lemmas
+
+
+def ReadProperNouns(filename, normalize_opts=NormalizeOptions(),
+                    input_format=None):
+    """Return the set of normalized proper nouns in a word file.
+
+      Names with spaces, hyphens or apostrophes ("new york", "o'neil")
+      can't fill a row, so they're left out.
+    """
+
+    return set([word for word in ReadScrabbleSet(filename, normalize_opts,
+                                                 input_format)
+                if word.isalpha()])
+
+
+def WithoutLetters(words, letters):
+    """Return the set of words that don't use any of letters."""
+
+    letters = set(letters)
+    return set([word for word in words if letters.isdisjoint(word)])
+
+
+# (filename, input_format, alphabet, strip_accents) -> ReadScrabbleSet result,
+# so --word_lens only parses each word file once.
+_scrabble_set_cache = {}
+
+
+def ReadScrabbleSet(scrabble_words_file, normalize_opts=NormalizeOptions(),
+                    input_format=None):
+    """Return the set of normalized words in the scrabble file (or any other
+      list of words).
+
+      Each file is only read once. Callers get their own copy of the set.
+    """
+
+    key = (scrabble_words_file, input_format,
+           frozenset(normalize_opts.alphabet or []) or None,
+           normalize_opts.strip_accents)
+    if key not in _scrabble_set_cache:
+        scrabble_set = set()
+        for raw_word in ReadWordFile(scrabble_words_file, input_format):
+            word = NormalizeWord(raw_word, normalize_opts)
+            if word is not None:
+                scrabble_set.add(word)
+        _scrabble_set_cache[key] = frozenset(scrabble_set)
+    return set(_scrabble_set_cache[key])
+
+
+def PickTopWords(freq_csv_file, scrabble_set, top_n, word_len,
+                 normalize_opts=NormalizeOptions(), freq_format=None,
+                 per_letter=False):
+    """Return the top_n most frequent word_len letter words in scrabble_set.
+
+      With per_letter, each first letter gets its own cutoff of top_n / 26
+      (rounded up), so letters with few words give fewer than top_n in
+      all. The words still come out most frequent first.
+
+      Args: as for GetWorkingWords
+    """
+
+    letter_cutoff = -(-top_n // 26)
+    letter_counts = collections.Counter()
+    working_words = []
+    for raw_word in ReadWordFile(freq_csv_file, freq_format):
+        # Assume it's sorted by frequency, so we just count the top N
+        # that are scrabble words
+        word = NormalizeWord(raw_word, normalize_opts)
+        if word is None or len(word) != word_len:
+            continue
+        if word in scrabble_set:
+            if per_letter:
+                if letter_counts[word[0]] >= letter_cutoff:
+                    continue
+                letter_counts[word[0]] += 1
+            working_words.append(word)
+            if len(working_words) >= top_n:
+                break
+    return working_words
+
+
+def WordAlphabet(word_list):
+    """Return the sorted list of chars used by any word in word_list.
+
+      Nothing assumes a-z, so this is how we find the alphabet for word lists
+      in other languages (e.g. with ñ in them).
+    """
+
+    return sorted(set("".join(word_list)))
+
+
 class WordTrie(dict):
     """ Datastructure holding a trie of words all the same length.
 
       This is stored as as a dict of single chars, each pointing to a trie,
       making up valid words.  The last char of a word has a value of "True"
-      rather than another dict.  
+      rather than another dict. Keys are whatever chars the words use, so
+      this works the same for any alphabet.
 
       I tried another implementation that uses 26-element arrays in stead of
       dicts, but it's 3x slower for some reason (Python's dicts are fast).
@@ -99,6 +443,185 @@ class WordTrie(dict):
             # The last leaf node is a "bool True" rather than an empty WordTrie.
             cur_trie.setdefault(word[-1], True)
 
+    def WordCount(self):
+        """Return the number of complete words below this node.
+
+          This is cached after the first call, so only call it once the trie
+          is fully built.
+        """
+        if not hasattr(self, '_word_count'):
+            self._word_count = sum([1 if node == True else node.WordCount()
+                                    for node in self.values()])
+        return self._word_count
+
+    def CharsByWordCount(self):
+        """Return this node's chars, the ones with the most words below first.
+
+          Cached like WordCount.
+        """
+        if not hasattr(self, '_chars_by_word_count'):
+            self._chars_by_word_count = sorted(
+                self.keys(),
+                key=lambda c: -(1 if self[c] == True else self[c].WordCount()))
+        return self._chars_by_word_count
+
+    def LettersBelow(self):
+        """Return the set of chars used anywhere below this node.
+
+          Cached like WordCount.
+        """
+        if not hasattr(self, '_letters_below'):
+            self._letters_below = set(self.keys())
+            for node in self.values():
+                if node != True:
+                    self._letters_below |= node.LettersBelow()
+        return self._letters_below
+
+    def MinRank(self, word_ranks, prefix=""):
+        """Return the lowest rank of any word below this node.
+
+          This depends on the path to the node, so it's wrong after Minimize.
+
+          Args:
+            word_ranks: dict of word -> frequency rank. Always pass the same
+              one, since the answer is cached like WordCount.
+            prefix: the chars leading to this node from the root
+        """
+        if not hasattr(self, '_min_rank'):
+            self._min_rank = min([
+                word_ranks[prefix + c] if node == True else
+                node.MinRank(word_ranks, prefix + c)
+                for c, node in self.items()])
+        return self._min_rank
+
+    def Minimize(self):
+        """Merge identical subtrees, turning the trie into a DAWG.
+
+          Words that end the same way ("-ing", "-ed", "-s") share one copy
+          of their shared ending, so the trie takes several times less
+          memory. Lookups are the same as before. Only subtrees with their
+          chars in the same order are merged, so walking the trie gives the
+          same order as well.
+
+          Call this once, right after building the trie, before any of the
+          cached methods. MinRank can't be used afterwards, since it caches
+          an answer that depends on the path to the node.
+        """
+        merged = {}
+
+        def Merge(node):
+            for c, child in node.items():
+                if child != True:
+                    node[c] = Merge(child)
+            key = tuple([(c, True if child == True else id(child))
+                         for c, child in node.items()])
+            return merged.setdefault(key, node)
+
+        for c, child in self.items():
+            if child != True:
+                self[c] = Merge(child)
+
+    def WordLen(self):
+        """Return the length of the words in this trie (0 if it's empty).
+        """
+        node = self
+        word_len = 0
+        while node != True and node:
+            node = next(iter(node.values()))
+            word_len += 1
+        return word_len
+
+    def PrefixNode(self, prefix):
+        """Return the node reached by following prefix down from here.
+
+          Returns:
+            The WordTrie node, True if prefix is a whole word, or None if no
+            word starts with prefix.
+        """
+        node = self
+        for c in prefix:
+            if node == True or c not in node:
+                return None
+            node = node[c]
+        return node
+
+    def PrefixCount(self, prefix):
+        """Return how many words in the trie start with prefix.
+
+          Args:
+            prefix: string, up to the length of the words. "" counts them all.
+          Returns:
+            Integer count, 0 if no word has that prefix.
+        """
+        node = self.PrefixNode(prefix)
+        if node is None:
+            return 0
+        return 1 if node == True else node.WordCount()
+
+
+class WordIndex:
+    """Index of same-length words by (position, letter).
+
+      The trie only answers questions about prefixes. This can find the words
+      matching any pattern of known and unknown letters, which is what a
+      search that doesn't fill rows in order needs.
+
+      Sets of words are bitsets: Python ints with bit i set if self.words[i]
+      is in the set. Finding the words that fit a pattern is then an AND of
+      one int per known letter, which is much faster than intersecting sets
+      of strings. Words(bits) turns a bitset back into words.
+    """
+
+    def __init__(self, word_list):
+        self.words = sorted(set(word_list))
+        self.word_len = len(word_list[0]) if word_list else 0
+        self.all_bits = (1 << len(self.words)) - 1
+        # Set the bits in bytearrays first; OR-ing them into ints one word at
+        # a time would copy the whole int every time.
+        bytes_by_letter = collections.defaultdict(
+            lambda: bytearray((len(self.words) + 7) // 8))
+        for word_num, word in enumerate(self.words):
+            for i, c in enumerate(word):
+                bytes_by_letter[(i, c)][word_num // 8] |= 1 << (word_num % 8)
+        self._bits = dict([(key, int.from_bytes(bits, "little"))
+                           for key, bits in bytes_by_letter.items()])
+        self._letters = [sorted([c for i, c in self._bits if i == pos])
+                         for pos in range(self.word_len)]
+
+    def Words(self, bits):
+        """Return the list of words in a bitset, in sorted order."""
+        # Take off the lowest bit each time. There are usually only a few
+        # words, so this beats looking at every bit.
+        words = []
+        while bits:
+            lowest_bit = bits & -bits
+            words.append(self.words[lowest_bit.bit_length() - 1])
+            bits ^= lowest_bit
+        return words
+
+    def LetterBits(self, pos, c):
+        """Return the bitset of words with char c at position pos."""
+        return self._bits.get((pos, c), 0)
+
+    def LettersAt(self, bits, pos):
+        """Return the set of chars at position pos in any word in bits."""
+        return set([c for c in self._letters[pos]
+                    if bits & self._bits[(pos, c)]])
+
+    def MatchingLetterSets(self, letter_sets):
+        """Return the bitset of words whose letter i is in letter_sets[i], for
+          all i.
+        """
+        bits = self.all_bits
+        for i, letters in enumerate(letter_sets):
+            position_bits = 0
+            for c in letters:
+                position_bits |= self._bits.get((i, c), 0)
+            bits &= position_bits
+            if not bits:
+                break
+        return bits
+
 
 def GenWordsFromValidChars(word_trie, valid_next_row_chars):
     """Return a generator that produces valid words.
@@ -140,7 +663,50 @@ def GenWordsFromValidChars(word_trie, valid_next_row_chars):
         # print("  G%d:  return" % depth)
 
 
-def GenSquares(word_trie, start_word):
+class AnyCharNode(dict):
+    """Stands in for a column's trie node when that column is unconstrained.
+
+      It maps every char in the alphabet back to itself, so any letters can
+      go down that column. Used for finding near misses.
+    """
+
+    def __init__(self, alphabet):
+        super().__init__({c: self for c in alphabet})
+
+    def CharsByWordCount(self):
+        return sorted(self.keys())
+
+
+class FirstRowTimeout(Exception):
+    """Raised inside GenSubSquares when a start word has used up its time.
+    """
+
+
+def LetterRanks(words):
+    """Return a dict of char -> rank, 0 for the char used most in words."""
+
+    counts = collections.Counter("".join(words))
+    return dict([(c, rank) for rank, (c, _) in enumerate(counts.most_common())])
+
+
+def OrderedChars(node, order_by_branching=False, letter_ranks=None):
+    """Return the chars below a trie node, in the order to try them.
+
+      Args: see GenSubSquares
+    """
+
+    if order_by_branching:
+        return node.CharsByWordCount()
+    if letter_ranks is not None:
+        return sorted(node.keys(),
+                      key=lambda c: (letter_ranks.get(c, len(letter_ranks)), c))
+    return sorted(node.keys())
+
+
+def GenSquares(word_trie, start_word, fill_order="rows", timeout_ms=None,
+               order_by_branching=False, symmetric_only=False,
+               column_trie=None, cell_filter=None, word_indexes=None,
+               run_deadline=None, distinct_words=False, letter_ranks=None):
     """Generate all the squares that have start_word at the top.
 
       A valid square is a N words of length N that also form
@@ -149,6 +715,32 @@ def GenSquares(word_trie, start_word):
       Args:
         word_trie: WordTrie, the root
         start_word: String of one word from the trie
+        column_trie: Optional WordTrie for the column words, if they're not
+          from word_trie. If its words are M long, this makes M x N
+          rectangles rather than squares. With fill_order "columns",
+          word_trie has the column words and column_trie the row ones.
+        cell_filter: Optional extra constraint on the letters in each cell,
+          see GenSubSquares.
+        word_indexes: Optional (row WordIndex, column WordIndex). If given,
+          search with GenMostConstrainedSquares instead of row by row. That
+          ignores order_by_branching, symmetric_only and cell_filter.
+        fill_order: "rows" to fill the square in a row at a time, below
+          start_word as the top row. "columns" to fill it in a column at a
+          time, right of start_word as the left column.
+        timeout_ms: Optional time budget for this start word. If the search
+          takes longer, we log it and stop early, so one bad start word
+          can't stall a whole run.
+        order_by_branching: If True, try the letters that leave the most
+          column words open first, which tends to find squares sooner.
+        symmetric_only: If True, only make single squares (columns the same
+          as the rows), with GenSymmetricSubSquares. That's much faster than
+          making all the squares and filtering.
+        run_deadline: Optional time.time() value for the whole run. Past it,
+          we quietly stop.
+        distinct_words: If True, don't use a word in two rows, see
+          GenSubSquares. Not for symmetric_only or word_indexes.
+        letter_ranks: Optional dict of char -> LetterRanks rank, to try the
+          letters in that order, see GenSubSquares
 
       Returns:
         A generator that yields "squares," where a square is just a list
@@ -159,20 +751,60 @@ def GenSquares(word_trie, start_word):
     log = Logger(start_word)
     log.log("--- TOP START WORD ---")
 
+    if column_trie is None:
+        column_trie = word_trie
+
     column_trie_nodes = []
     for c in start_word:
         # Look for possible vertical words
-        if c not in word_trie:
-            log.log(" --> NO MATCH FOR TOP START WORD %s of %s" %
-                    (c, start_word))
+        if c not in column_trie:
+            log.log(" --> NO MATCH FOR TOP START WORD %s of %s",
+                    c, start_word)
+            return
+        column_trie_nodes.append(column_trie.get(c))
+
+    deadline = None
+    if timeout_ms is not None:
+        deadline = time.time() + timeout_ms / 1000
+    if run_deadline is not None:
+        deadline = min(deadline or run_deadline, run_deadline)
+
+    try:
+        if word_indexes is not None:
+            for sq in GenMostConstrainedSquares(word_indexes[0], word_indexes[1],
+                                                start_word, deadline):
+                yield TransposeSquare(sq) if fill_order == "columns" else sq
+            return
+
+        if symmetric_only:
+            # Symmetric squares read the same both ways, so fill_order
+            # doesn't matter.
+            for sq in GenSymmetricSubSquares([start_word], column_trie_nodes,
+                                             deadline, order_by_branching,
+                                             cell_filter, letter_ranks):
+                yield [start_word] + sq
             return
-        column_trie_nodes.append(word_trie.get(c))
 
-    for sq in GenSubSquares(word_trie, start_word, column_trie_nodes, []):
-        yield [start_word] + sq
+        for sq in GenSubSquares(word_trie, start_word, column_trie_nodes, [],
+                                deadline, order_by_branching, cell_filter,
+                                distinct_words, letter_ranks):
+            if fill_order == "columns":
+                # Rows and columns come from the same trie, so filling columns
+                # is the same search as filling rows, with the result
+                # transposed.
+                yield TransposeSquare([start_word] + sq)
+            else:
+                yield [start_word] + sq
+    except FirstRowTimeout:
+        if run_deadline is not None and time.time() > run_deadline:
+            return
+        print("Skipping the rest of start word %s after %d ms" %
+              (start_word, timeout_ms))
 
 
-def GenSubSquares(word_trie, start_word, column_trie_nodes=[], log_prefix=[]):
+def GenSubSquares(word_trie, start_word, column_trie_nodes=[], log_prefix=[],
+                  deadline=None, order_by_branching=False, cell_filter=None,
+                  distinct_words=False, letter_ranks=None):
     """Generate all the partial squares that have start_word at the top, recursively.
 
       A valid square is a N words of length N that also form valid words
@@ -185,6 +817,19 @@ def GenSubSquares(word_trie, start_word, column_trie_nodes=[], log_prefix=[]):
         columne_trie_nodes: list of N nodes from the word-trie, corresponding to
           each char for the start_word
         log_prefix: Optional list of string to join+prefix output. We'll add start_word to it.
+          This is also the list of rows above start_word.
+        deadline: Optional time.time() value. Past it, we raise FirstRowTimeout.
+        order_by_branching: If True, order each position's valid chars by how
+          many column words they leave open, most first.
+        cell_filter: Optional function(rows, valid_next_row_chars) that
+          returns a (possibly) narrower valid_next_row_chars, given all the
+          rows so far. This is how extra constraints prune the search.
+        distinct_words: If True, don't put a word in two rows. (A word as a
+          row and a column can only be spotted once the columns are complete,
+          so that's left to the caller.)
+        letter_ranks: Optional dict of char -> rank, from LetterRanks. If
+          given (and not order_by_branching), try each position's valid
+          chars lowest rank first, rather than alphabetically.
 
       Returns:
         Partial squares. It's a generator of list of words (rows) that make
@@ -194,11 +839,11 @@ def GenSubSquares(word_trie, start_word, column_trie_nodes=[], log_prefix=[]):
     # New log obj with start_word added to the prefix.
     # This helps us figure out where we are in the process.
     next_log_prefix = log_prefix + [start_word]
-    log = Logger('/'.join(next_log_prefix))
+    log = Logger(next_log_prefix)
 
     log.log("Sub square start")
-    # Last row?
-    if column_trie_nodes[0] == True:
+    # Last row? (Any column will do, unless it's an AnyCharNode.)
+    if column_trie_nodes[0] == True or column_trie_nodes[-1] == True:
         yield []
         return
 
@@ -207,15 +852,31 @@ def GenSubSquares(word_trie, start_word, column_trie_nodes=[], log_prefix=[]):
 
     # Compute valid_next_row_chars we'll iterate over.
     for this_node in column_trie_nodes:
-        valid_next_row_chars.append(sorted(this_node.keys()))
+        valid_next_row_chars.append(
+            OrderedChars(this_node, order_by_branching, letter_ranks))
 
-    log.log("valid_next_row_chars = %s" % ('-'.join([''.join(charlist) for charlist in
-            valid_next_row_chars])))
+    if cell_filter is not None:
+        valid_next_row_chars = cell_filter(next_log_prefix, valid_next_row_chars)
+
+    if LOG_DETAILS:
+        log.log("valid_next_row_chars = %s",
+                '-'.join([''.join(charlist) for charlist in
+                          valid_next_row_chars]))
+    if SEARCH_STATS is not None:
+        SEARCH_STATS["partial squares"] += 1
 
     # Look at all the valid words using the set of possible chars,
     # and see if there's a square that could be made from that word.
+    tried_a_row = False
     for row_word_to_try in GenWordsFromValidChars(word_trie, valid_next_row_chars):
-        log.log("  Trying %s" % row_word_to_try)
+        log.log("  Trying %s", row_word_to_try)
+        if deadline is not None and time.time() > deadline:
+            raise FirstRowTimeout()
+        if distinct_words and row_word_to_try in next_log_prefix:
+            continue
+        tried_a_row = True
+        if SEARCH_STATS is not None:
+            SEARCH_STATS["rows tried"] += 1
 
         # Get list of column trie nodes for this word
         next_column_trie_nodes = [column_trie_nodes[i].get(c) for i, c in
@@ -223,141 +884,3620 @@ def GenSubSquares(word_trie, start_word, column_trie_nodes=[], log_prefix=[]):
 
         # Recurse downward
         for sub_square in GenSubSquares(word_trie, row_word_to_try,
-                                        next_column_trie_nodes, next_log_prefix):
+                                        next_column_trie_nodes, next_log_prefix,
+                                        deadline, order_by_branching,
+                                        cell_filter, distinct_words,
+                                        letter_ranks):
             new_sub_square = [row_word_to_try] + sub_square
-            log.log(" SS: yeilding [%s]" % (" / ".join(new_sub_square)))
+            if LOG_DETAILS:
+                log.log(" SS: yeilding [%s]", " / ".join(new_sub_square))
             yield new_sub_square
+    if SEARCH_STATS is not None and not tried_a_row:
+        SEARCH_STATS["dead ends"] += 1
 
 
-def IsDoubleSquare(sq):
-    """ Figure out if this has columns that differ from the rows.
+def GenSymmetricSubSquares(rows, column_trie_nodes, deadline=None,
+                           order_by_branching=False, cell_filter=None,
+                           letter_ranks=None):
+    """Generate the rest of the symmetric squares below rows, recursively.
+
+      In a symmetric square row i is the same as column i, so the first i
+      letters of the next row are already set by the rows above it. We only
+      pick the rest, i.e. the upper triangle of the grid, which makes this
+      a much smaller search than GenSubSquares.
+
+      Args:
+        rows: list of the rows so far, at least one
+        column_trie_nodes: list of N nodes from the word-trie, for where each
+          column is after the rows so far. Only the ones at len(rows) and
+          later are used.
+        deadline: Optional time.time() value. Past it, we raise FirstRowTimeout.
+        order_by_branching, cell_filter, letter_ranks: see GenSubSquares
+
+      Returns:
+        A generator of lists of the rows to add below rows.
     """
 
-    for i, row_word in enumerate(sq):
-        col_word = "".join([word[i] for word in sq])
-        if col_word != row_word:
-            return True
+    row_num = len(rows)
+    if row_num == len(column_trie_nodes):
+        yield []
+        return
+
+    # The next row picks up from where column row_num is, and its remaining
+    # chars have to fit the columns to the right.
+    valid_next_row_chars = []
+    for this_node in column_trie_nodes[row_num:]:
+        valid_next_row_chars.append(
+            OrderedChars(this_node, order_by_branching, letter_ranks))
 
-    return False
+    prefix = "".join([row[row_num] for row in rows])
+    if cell_filter is not None:
+        # The filter wants the whole row, so put the fixed chars in front,
+        # and make sure it still allows them.
+        full_row_chars = cell_filter(rows, [[c] for c in prefix] +
+                                     valid_next_row_chars)
+        if not all([c in full_row_chars[i] for i, c in enumerate(prefix)]):
+            return
+        valid_next_row_chars = full_row_chars[row_num:]
 
+    for suffix in GenWordsFromValidChars(column_trie_nodes[row_num],
+                                         valid_next_row_chars):
+        if deadline is not None and time.time() > deadline:
+            raise FirstRowTimeout()
 
-def WordsAreUnique(sq, is_double_square):
-    """Figure out if there are any shared words in rows + cols.
+        next_column_trie_nodes = (
+            column_trie_nodes[:row_num + 1] +
+            [column_trie_nodes[row_num + 1 + i].get(c)
+             for i, c in enumerate(suffix[1:])])
+        row_word = prefix + suffix
+        for sub_square in GenSymmetricSubSquares(rows + [row_word],
+                                                 next_column_trie_nodes,
+                                                 deadline, order_by_branching,
+                                                 cell_filter, letter_ranks):
+            yield [row_word] + sub_square
 
-    If not is_double_square, this'll look at just rows.
+
+def GenMostConstrainedSquares(row_index, column_index, start_word,
+                              deadline=None):
+    """Generate squares with start_word on top, filling the hardest row next.
+
+      Rather than always filling the next row down, each step fills the empty
+      row that the fewest words fit, given the letters the columns can still
+      have. Rows aren't filled top down, so a column isn't just a prefix and
+      this can't use the trie; it matches patterns with WordIndexes instead.
+
+      Each column keeps the bitset of column words that still fit it, which
+      only shrinks as rows go in. The letters each empty cell can have come
+      from those, so a step costs one AND per column to keep them up to date.
+
+      Args:
+        row_index: WordIndex of the words that can be rows
+        column_index: WordIndex of the words that can be columns
+        start_word: the top row
+        deadline: Optional time.time() value. Past it, we raise FirstRowTimeout.
+
+      Returns:
+        A generator of squares (lists of row words).
     """
 
-    words = set()
-    for i, row_word in enumerate(sq):
-        if row_word in words:
-            return False
-        words.add(row_word)
+    def Fill(rows, column_bits):
+        empty_rows = [r for r, row in enumerate(rows) if row is None]
+        if not empty_rows:
+            yield list(rows)
+            return
+        if deadline is not None and time.time() > deadline:
+            raise FirstRowTimeout()
+
+        # The letters each empty cell can have, from the column words that
+        # still fit each column.
+        allowed = dict([(r, [column_index.LettersAt(bits, r)
+                             for bits in column_bits])
+                        for r in empty_rows])
 
-        if is_double_square:
-            col_word = "".join([word[i] for word in sq])
-            if col_word in words:
-                return False
-            words.add(col_word)
+        best_row = None
+        best_bits = None
+        best_count = None
+        for r in empty_rows:
+            bits = row_index.MatchingLetterSets(allowed[r])
+            if not bits:
+                return
+            count = bits.bit_count()
+            if best_count is None or count < best_count:
+                best_row = r
+                best_bits = bits
+                best_count = count
 
-    return True
+        for word in row_index.Words(best_bits):
+            rows[best_row] = word
+            yield from Fill(rows, [
+                bits & column_index.LetterBits(best_row, c)
+                for bits, c in zip(column_bits, word)])
+        rows[best_row] = None
 
+    yield from Fill([start_word] + [None] * (column_index.word_len - 1),
+                    [column_index.LetterBits(0, c) for c in start_word])
 
-def DoSomeBenchmarking(working_words, word_trie):
-    """Print out some benchmarks at how fast we can make squares
+
+def BeamScore(rows, column_trie_nodes, word_ranks, num_words):
+    """How promising a partial square is, for beam search. Higher is better.
+
+      Columns with more possible words left are likelier to get finished, so
+      this adds up the log of each open column's word count. Squares of
+      common words are nicer, so it takes off the rows' mean frequency rank,
+      as a fraction of the word list.
+    """
+
+    viability = sum([math.log(node.WordCount()) for node in column_trie_nodes
+                     if node != True])
+    rarity = sum([word_ranks[row] for row in rows]) / len(rows) / num_words
+    return viability - rarity
+
+
+def GenBeamSquares(row_trie, column_trie, start_words, beam_width, word_ranks,
+                   cell_filter=None):
+    """Generate some good squares fast, by only following the best few.
+
+      This is a beam search: at each depth (number of rows) it only keeps the
+      beam_width partial squares with the best BeamScore, across all the
+      start words, and only extends those. So it misses most squares, but
+      the ones it finds tend to be made of common words, and it's quick.
 
       Args:
-        working_words: list of words to use
-        word_trie: root node of WordTrie
+        row_trie: WordTrie for the row words
+        column_trie: WordTrie for the column words
+        start_words: list of the words that can be the top row
+        beam_width: how many partial squares to keep at each depth
+        word_ranks: dict from WordRanks
+        cell_filter: Optional extra constraint, see GenSubSquares
+
+      Returns:
+        A generator of squares, best first.
     """
 
-    random.seed(123)
-    random.shuffle(working_words)
+    num_words = len(word_ranks)
+    beam = []
+    for start_word in start_words:
+        column_trie_nodes = [column_trie.get(c) for c in start_word]
+        if None not in column_trie_nodes:
+            beam.append(([start_word], column_trie_nodes))
 
-    start_time = time.time()
-    squares = 0
-    pos = 0
-    target_time = start_time + 10  # 10 seconds
-    while time.time() < target_time:
-        for sq in GenSquares(word_trie, working_words[pos]):
-            squares += 1
+    def Score(partial_square):
+        return BeamScore(partial_square[0], partial_square[1], word_ranks,
+                         num_words)
 
-        pos += 1
-        if pos >= len(working_words):
-            pos = 0
+    for _ in range(column_trie.WordLen() - 1):
+        beam = sorted(beam, key=Score, reverse=True)[:beam_width]
+        next_beam = []
+        for rows, column_trie_nodes in beam:
+            valid_next_row_chars = [sorted(node.keys())
+                                    for node in column_trie_nodes]
+            if cell_filter is not None:
+                valid_next_row_chars = cell_filter(rows, valid_next_row_chars)
+            for row_word in GenWordsFromValidChars(row_trie,
+                                                   valid_next_row_chars):
+                next_beam.append((rows + [row_word],
+                                  [node.get(c) for node, c in
+                                   zip(column_trie_nodes, row_word)]))
+        beam = next_beam
 
-    dur = time.time() - start_time
-    print("Ran %d squares in %.1f sec, or %.0f squares/sec" % (squares, dur,
-          squares / dur))
+    for rows, _ in sorted(beam, key=Score, reverse=True)[:beam_width]:
+        yield rows
 
 
-def main():
-    """ Main
+def GenAnnealedSquares(row_words, column_trie, rng, restarts, steps):
+    """Generate squares by simulated annealing, with random restarts.
+
+      Each restart starts from random row words, then keeps swapping a random
+      row for another word: usually one that differs from it by one letter,
+      sometimes any random word. Swaps that make the columns better are
+      always kept, and ones that make them worse are kept less and less
+      often as the temperature drops. A column's cost is how many letters
+      from the bottom it is from being a valid prefix, so 0 means a word.
+
+      It doesn't find every square, and can find the same one twice (only
+      the first is yielded), but it doesn't have to search the whole tree,
+      so it can find big squares that an exhaustive search never gets to.
+
+      Args:
+        row_words: list of words that can be rows
+        column_trie: WordTrie for the column words
+        rng: random.Random to use
+        restarts: how many times to start over from a random grid
+        steps: how many swaps to try in each restart
+
+      Returns:
+        A generator of squares, in the order they're found.
     """
 
-    parser = argparse.ArgumentParser()
-    # Inputs
-    parser.add_argument('--freq_csv_file', type=str,
-                        default='unigram_freq.csv',
-                        help="CSV file where the first row is words, "
-                        "sorted in most popular first")
-    parser.add_argument('--scrabble_words_file', type=str,
-                        default='scrabble_words.txt',
-                        help="Text file of valid words to use")
+    num_rows = column_trie.WordLen()
 
-    # Knobs
-    parser.add_argument('--top_n', type=int, default=5000,
-                        help="Cutoff for N most popular words to use")
-    parser.add_argument('--word_len', type=int, default=5,
-                        help="Len of words to use")
-    parser.add_argument('--double_squares_only', action="store_true",
-                        help="Print only valid double squares")
+    # Words that are the same except at one position, keyed by the word with
+    # "." at that position.
+    one_letter_apart = collections.defaultdict(list)
+    for word in row_words:
+        for i in range(len(word)):
+            one_letter_apart[word[:i] + "." + word[i + 1:]].append(word)
 
-    # Debugging options
-    parser.add_argument('--log_details', action="store_true")
-    parser.add_argument('--just_benchmark', action="store_true")
+    def NewRowWord(row_word):
+        if rng.random() < 0.2:
+            return rng.choice(row_words)
+        i = rng.randrange(len(row_word))
+        return rng.choice(one_letter_apart[row_word[:i] + "." + row_word[i + 1:]])
 
-    args = parser.parse_args()
-    LOG_DETAILS = args.log_details
+    def ColumnCost(rows, c):
+        node = column_trie
+        for depth, row in enumerate(rows):
+            node = node.get(row[c])
+            if node is None:
+                return num_rows - depth
+        return 0
 
-    working_words = GetWorkingWords(args.freq_csv_file, args.scrabble_words_file,
-                                    args.top_n, args.word_len)
+    found = set()
+    for _ in range(restarts):
+        rows = [rng.choice(row_words) for _ in range(num_rows)]
+        cost = sum([ColumnCost(rows, c) for c in range(len(rows[0]))])
+        for step in range(steps):
+            if cost == 0:
+                break
+            temperature = max(0.05, 0.5 * (1 - step / steps))
+            new_rows = list(rows)
+            r = rng.randrange(num_rows)
+            new_rows[r] = NewRowWord(rows[r])
+            new_cost = sum([ColumnCost(new_rows, c) for c in range(len(rows[0]))])
+            if (new_cost <= cost or
+                    rng.random() < math.exp((cost - new_cost) / temperature)):
+                rows = new_rows
+                cost = new_cost
 
-    word_trie = WordTrie(working_words)
+        if cost == 0 and tuple(rows) not in found:
+            found.add(tuple(rows))
+            yield rows
 
-    if args.just_benchmark:
-        DoSomeBenchmarking(working_words, word_trie)
-        return
 
-    # Example valid 6x6 square:
-    #  market
-    #  avenue
-    #  relics
-    #  knight
-    #  euchre
-    #  rested
+def GenSampledSquares(row_trie, column_trie, start_words, rng, num_samples,
+                      num_walks, cell_filter=None):
+    """Return about num_samples squares, picked roughly uniformly at random.
 
-    print("Generating Squares..\n")
-    sq_num = 0
-    for word_num, start_word in enumerate(working_words):
-        for sq in GenSquares(word_trie, start_word):
-            # Collect some classifications of this sq.
-            is_double_square = IsDoubleSquare(sq)
-            words_are_unique = WordsAreUnique(sq, is_double_square)
-            if args.double_squares_only and (not is_double_square
-                                             or not words_are_unique):
-                continue
-            desc = "WordSquare %d from word %d/%d: %s-word-square, %s" % (
-                sq_num, word_num, len(working_words),
-                "double" if is_double_square else "single",
-                "unique" if words_are_unique else "non-unique")
-            print(desc)
-            for word in sq:
-                print("  " + word)
-            print("")
-            sq_num += 1
+      Enumerating squares always gives them in word list order. This instead
+      does num_walks random walks down the search tree, picking a random
+      valid row word at each step. A walk that finishes a square got there
+      with probability 1 / (product of the number of choices at each step),
+      so some squares are much likelier than others. To even that out, each
+      finished walk adds that product to its square's weight, and we pick
+      the samples weighted by those (sampling importance resampling). A
+      square's expected weight is then num_walks whichever square it is, so
+      this is close to uniform when there are enough walks.
 
-    print("Made %d squares" % sq_num)
+      Args:
+        row_trie: WordTrie for the row words
+        column_trie: WordTrie for the column words
+        start_words: list of the words that can be the top row
+        rng: random.Random to use
+        num_samples: how many squares to return
+        num_walks: how many random walks to do. More is closer to uniform.
+        cell_filter: Optional extra constraint, see GenSubSquares
+
+      Returns:
+        List of up to num_samples different squares.
+    """
+
+    num_rows = column_trie.WordLen()
+    weights = {}
+    for _ in range(num_walks):
+        rows = [rng.choice(start_words)]
+        weight = len(start_words)
+        column_trie_nodes = [column_trie.get(c) for c in rows[0]]
+        while None not in column_trie_nodes and len(rows) < num_rows:
+            valid_next_row_chars = [sorted(node.keys())
+                                    for node in column_trie_nodes]
+            if cell_filter is not None:
+                valid_next_row_chars = cell_filter(rows, valid_next_row_chars)
+            row_words = list(GenWordsFromValidChars(row_trie,
+                                                    valid_next_row_chars))
+            if not row_words:
+                break
+            weight *= len(row_words)
+            row_word = rng.choice(row_words)
+            rows.append(row_word)
+            column_trie_nodes = [node.get(c) for node, c in
+                                 zip(column_trie_nodes, row_word)]
+
+        if len(rows) == num_rows and None not in column_trie_nodes:
+            key = tuple(rows)
+            weights[key] = weights.get(key, 0) + weight
+
+    samples = []
+    while weights and len(samples) < num_samples:
+        sq = rng.choices(list(weights), list(weights.values()))[0]
+        del weights[sq]
+        samples.append(list(sq))
+    return samples
+
+
+def GenSatSquares(row_words, column_words):
+    """Generate all the squares by handing the problem to a SAT solver.
+
+      This is for comparing against the search engines. It needs the
+      optional python-sat package. The encoding has a variable for each
+      (row, word), (column, word) and (cell, letter): each row and column
+      has exactly one word, a chosen word sets the letters of its cells, and
+      a cell has at most one letter. After each solution we add a clause
+      blocking that set of rows, until there are no more.
+
+      Args:
+        row_words: list of words that can be rows
+        column_words: list of words that can be columns
+
+      Returns:
+        A generator of squares, in whatever order the solver finds them.
+    """
+
+    from pysat.card import CardEnc, EncType
+    from pysat.solvers import Solver
+
+    num_rows = len(column_words[0])
+    num_cols = len(row_words[0])
+    alphabet = WordAlphabet(row_words + column_words)
+    next_var = [0]
+
+    def NewVar():
+        next_var[0] += 1
+        return next_var[0]
+
+    row_vars = [[NewVar() for _ in row_words] for _ in range(num_rows)]
+    column_vars = [[NewVar() for _ in column_words] for _ in range(num_cols)]
+    cell_vars = [[dict([(c, NewVar()) for c in alphabet])
+                  for _ in range(num_cols)] for _ in range(num_rows)]
+
+    clauses = []
+    for line_vars in row_vars + column_vars:
+        cnf = CardEnc.equals(lits=line_vars, bound=1, top_id=next_var[0],
+                             encoding=EncType.seqcounter)
+        next_var[0] = max(next_var[0], cnf.nv)
+        clauses.extend(cnf.clauses)
+    for r in range(num_rows):
+        for c in range(num_cols):
+            letter_vars = list(cell_vars[r][c].values())
+            for i, var in enumerate(letter_vars):
+                for other_var in letter_vars[i + 1:]:
+                    clauses.append([-var, -other_var])
+            for k, word in enumerate(row_words):
+                clauses.append([-row_vars[r][k], cell_vars[r][c][word[c]]])
+            for k, word in enumerate(column_words):
+                clauses.append([-column_vars[c][k], cell_vars[r][c][word[r]]])
+
+    with Solver(name='g3', bootstrap_with=clauses) as solver:
+        while solver.solve():
+            true_vars = set([var for var in solver.get_model() if var > 0])
+            chosen = [[var for var in row_vars[r] if var in true_vars][0]
+                      for r in range(num_rows)]
+            yield [row_words[row_vars[r].index(var)]
+                   for r, var in enumerate(chosen)]
+            solver.add_clause([-var for var in chosen])
+
+
+def GenCubeLayerSquares(word_trie, pillar_trie_nodes, first_row=None):
+    """Generate the word squares that fit on top of a partial cube.
+
+      Just like a normal square, except each cell (y, x) also has to continue
+      the pillar word going down through that cell.
+
+      Args:
+        word_trie: WordTrie, the root
+        pillar_trie_nodes: NxN list of lists of trie nodes, for where each
+          pillar is after the layers so far
+        first_row: Optional word that has to be the top row of the square
+
+      Returns:
+        A generator of squares (lists of N words)
+    """
+
+    n = len(pillar_trie_nodes)
+
+    def GenRows(rows, column_trie_nodes):
+        y = len(rows)
+        if y == n:
+            yield []
+            return
+
+        valid_next_row_chars = []
+        for x in range(n):
+            valid_next_row_chars.append(sorted(
+                set(column_trie_nodes[x].keys()) &
+                set(pillar_trie_nodes[y][x].keys())))
+
+        if y == 0 and first_row is not None:
+            if not all([c in valid_next_row_chars[x]
+                        for x, c in enumerate(first_row)]):
+                return
+            row_words = [first_row]
+        else:
+            row_words = GenWordsFromValidChars(word_trie, valid_next_row_chars)
+
+        for row_word in row_words:
+            next_column_trie_nodes = [column_trie_nodes[x].get(c)
+                                      for x, c in enumerate(row_word)]
+            for sub_square in GenRows(rows + [row_word],
+                                      next_column_trie_nodes):
+                yield [row_word] + sub_square
+
+    yield from GenRows([], [word_trie] * n)
+
+
+def GenCubes(word_trie, start_word):
+    """Generate NxNxN letter cubes with start_word along the top front edge.
+
+      Every row, column and pillar (the lines through the layers) of a cube
+      is a word. This is built layer by layer: each layer is a word square,
+      and the pillars are kept valid through the trie, like the columns are
+      in a square.
+
+      Args:
+        word_trie: WordTrie, the root
+        start_word: String of one word from the trie
+
+      Returns:
+        A generator of cubes, as lists of N layers, each a square (a list of
+        N words).
+    """
+
+    n = len(start_word)
+
+    def GenLayers(layers, pillar_trie_nodes):
+        if len(layers) == n:
+            yield []
+            return
+
+        first_row = start_word if not layers else None
+        for layer in GenCubeLayerSquares(word_trie, pillar_trie_nodes,
+                                         first_row):
+            next_pillar_trie_nodes = [
+                [pillar_trie_nodes[y][x].get(c) for x, c in enumerate(row)]
+                for y, row in enumerate(layer)]
+            for sub_cube in GenLayers(layers + [layer], next_pillar_trie_nodes):
+                yield [layer] + sub_cube
+
+    yield from GenLayers([], [[word_trie] * n for _ in range(n)])
+
+
+def PrintCubes(working_words, word_trie, limit=0, count_only=False):
+    """Print all the word cubes for all the start words, layers side by side.
+
+      Args:
+        limit: if > 0, stop after this many cubes
+        count_only: if True, only print how many there are
+    """
+
+    print("Generating Cubes..\n")
+    num_cubes = 0
+    for word_num, start_word in enumerate(working_words):
+        for cube in GenCubes(word_trie, start_word):
+            if not count_only:
+                print("WordCube %d from word %d/%d:" %
+                      (num_cubes, word_num, len(working_words)))
+                for y in range(len(start_word)):
+                    print("  " + "  ".join([layer[y] for layer in cube]))
+                print("")
+            num_cubes += 1
+            if num_cubes == limit:
+                print("Stopped at the --limit of %d cubes" % limit)
+                print("Made %d cubes" % num_cubes)
+                return
+
+    print("Made %d cubes" % num_cubes)
+
+
+def DiagonalCellFilter(word_trie, main_diagonal, anti_diagonal):
+    """Return a cell_filter (see GenSubSquares) that makes diagonals words.
+
+      Args:
+        word_trie: WordTrie, the root
+        main_diagonal: if True, the top left to bottom right diagonal has to
+          be a word from word_trie
+        anti_diagonal: if True, so does the top right to bottom left one
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        row_num = len(rows)
+        n = len(valid_next_row_chars)
+        valid_next_row_chars = list(valid_next_row_chars)
+        diagonals = []
+        if main_diagonal:
+            diagonals.append(lambda i: i)
+        if anti_diagonal:
+            diagonals.append(lambda i: n - 1 - i)
+
+        for pos in diagonals:
+            node = word_trie.PrefixNode("".join([row[pos(i)]
+                                                 for i, row in enumerate(rows)]))
+            valid_next_row_chars[pos(row_num)] = [
+                c for c in valid_next_row_chars[pos(row_num)]
+                if node is not None and c in node]
+        return valid_next_row_chars
+
+    return Filter
+
+
+def RotationalCellFilter(rows, valid_next_row_chars):
+    """A cell_filter (see GenSubSquares) for squares with 180 degree symmetry.
+
+      Such a square looks the same turned upside down, so row n-1-i is row i
+      backwards. Once the top half is placed, every cell in the bottom half
+      is forced, which cuts the search roughly in half.
+    """
+
+    n = len(valid_next_row_chars)
+    row_num = len(rows)
+    no_chars = [[] for _ in range(n)]
+
+    # For odd n, the middle row must be a palindrome. It's placed freely,
+    # so check it as soon as we're past it.
+    if n % 2 == 1 and row_num == n // 2 + 1:
+        middle_row = rows[n // 2]
+        if middle_row != middle_row[::-1]:
+            return no_chars
+
+    mirror_row_num = n - 1 - row_num
+    if mirror_row_num < row_num:
+        return [[c] if c in chars else []
+                for c, chars in zip(rows[mirror_row_num][::-1],
+                                    valid_next_row_chars)]
+    return valid_next_row_chars
+
+
+def PropagateCellDomains(row_trie, column_trie, start_word):
+    """Work out which letters can possibly go in each cell, before searching.
+
+      This is AC-3 style constraint propagation: a letter stays in a cell's
+      domain only if some row word and some column word through that cell
+      fit all the current domains, until nothing changes. Start words with
+      an empty domain somewhere are dead without any search at all.
+
+      In practice the row-by-row search already prunes well, so this costs
+      more time than it saves; it's mostly useful to look at the domains.
+
+      Args:
+        row_trie: WordTrie of the words that can be rows
+        column_trie: WordTrie of the words that can be columns
+        start_word: the top row
+      Returns:
+        Rows x columns list of lists of sets of letters, or None if some
+        cell has no possible letter (so there are no squares).
+    """
+
+    num_rows = column_trie.WordLen()
+    num_cols = len(start_word)
+    domains = [[set(start_word[c]) if r == 0 else None for c in range(num_cols)]
+               for r in range(num_rows)]
+
+    def Narrow(trie, line_domains):
+        """Return the letters each position can have, from words that fit."""
+        letters = [set() for _ in line_domains]
+
+        def Walk(node, depth):
+            found = False
+            chars = node.keys()
+            if line_domains[depth] is not None:
+                chars = line_domains[depth] & chars
+            for c in chars:
+                child = node[c]
+                if child == True or Walk(child, depth + 1):
+                    letters[depth].add(c)
+                    found = True
+            return found
+
+        Walk(trie, 0)
+        return letters
+
+    changed = True
+    while changed:
+        changed = False
+        # Columns first, since they start out constrained by start_word.
+        for c in range(num_cols):
+            column_domains = [domains[r][c] for r in range(num_rows)]
+            for r, letters in enumerate(Narrow(column_trie, column_domains)):
+                if domains[r][c] is None or letters < domains[r][c]:
+                    domains[r][c] = letters
+                    changed = True
+        for r in range(num_rows):
+            for c, letters in enumerate(Narrow(row_trie, domains[r])):
+                if letters < domains[r][c]:
+                    domains[r][c] = letters
+                    changed = True
+        if any([not d for row in domains for d in row]):
+            return None
+
+    return domains
+
+
+def DomainCellFilter(domains):
+    """Return a cell_filter (see GenSubSquares) that keeps to the domains.
+
+      Args:
+        domains: from PropagateCellDomains
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        row_domains = domains[len(rows)]
+        return [[c for c in chars if c in d]
+                for chars, d in zip(valid_next_row_chars, row_domains)]
+
+    return Filter
+
+
+def MatchesPattern(word, pattern):
+    """Return True if word fits pattern, where "." in pattern is any char."""
+
+    return len(word) == len(pattern) and all(
+        [p == "." or p == c for c, p in zip(word, pattern)])
+
+
+def PatternCellFilter(row_patterns):
+    """Return a cell_filter (see GenSubSquares) that makes rows fit patterns.
+
+      Args:
+        row_patterns: dict of row number (from 0) -> pattern string, with
+          "." for any letter. Row 0 is the start word, which doesn't go
+          through cell filters, so check it with MatchesPattern.
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        pattern = row_patterns.get(len(rows))
+        if pattern is None:
+            return valid_next_row_chars
+        return [chars if p == "." else [p] if p in chars else []
+                for p, chars in zip(pattern, valid_next_row_chars)]
+
+    return Filter
+
+
+def AvoidsBannedLetters(word, bans):
+    """Return True if no letter of word is in the banned set for its cell."""
+
+    return not any([c in banned for c, banned in zip(word, bans)])
+
+
+def BannedLettersCellFilter(banned_cells):
+    """Return a cell_filter (see GenSubSquares) that keeps banned letters out.
+
+      Args:
+        banned_cells: from ReadBannedLetters, a list per row of the set of
+          letters each cell can't have. Row 0 is the start word, which
+          doesn't go through cell filters, so check it with
+          AvoidsBannedLetters.
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        bans = banned_cells[len(rows)]
+        return [[c for c in chars if c not in banned]
+                for chars, banned in zip(valid_next_row_chars, bans)]
+
+    return Filter
+
+
+def MinDistinctLettersCellFilter(column_trie, min_distinct):
+    """Return a cell_filter (see GenSubSquares) for squares that use at
+      least min_distinct different letters.
+
+      Every cell below the rows so far is in some column, so the new
+      letters have to come from those below the columns' trie nodes. If
+      there aren't enough of those, or enough cells left, the branch is
+      dead. Once there are only just enough cells, every one of them has to
+      be a new letter. The final count is still checked on the finished
+      squares, since the last row isn't filtered after it's placed.
+
+      Args:
+        column_trie: WordTrie of the column words
+        min_distinct: the fewest different letters a square can use
+    """
+
+    num_rows = column_trie.WordLen()
+    # Letters as bits of an int, so the sets of them below each column are
+    # quick to combine and count.
+    letters = sorted(column_trie.LettersBelow())
+    letter_bits = dict([(c, 1 << i) for i, c in enumerate(letters)])
+    # Column prefix -> the bits of the letters below it, or None if no word
+    # has it. The search keeps coming back to the same column prefixes.
+    bits_below = {}
+
+    def BitsBelow(column):
+        if column not in bits_below:
+            node = column_trie.PrefixNode(column)
+            bits_below[column] = None if node is None else sum(
+                [letter_bits[c] for c in node.LettersBelow()])
+        return bits_below[column]
+
+    def Filter(rows, valid_next_row_chars):
+        used = set("".join(rows))
+        needed = min_distinct - len(used)
+        if needed <= 0:
+            return valid_next_row_chars
+
+        # Each column can add at most one new letter per cell it has left,
+        # and only ones below its trie node.
+        column_cells_left = num_rows - len(rows)
+        new_mask = ~sum([letter_bits.get(c, 0) for c in used])
+        new_below = 0
+        most_new = 0
+        for column in zip(*rows):
+            column_bits = BitsBelow(column)
+            if column_bits is None:
+                return [[] for _ in valid_next_row_chars]
+            column_bits &= new_mask
+            new_below |= column_bits
+            most_new += min(column_cells_left, column_bits.bit_count())
+        if min(new_below.bit_count(), most_new) < needed:
+            return [[] for _ in valid_next_row_chars]
+
+        cells_left = column_cells_left * len(valid_next_row_chars)
+        if needed > cells_left:
+            return [[] for _ in valid_next_row_chars]
+        if needed == cells_left:
+            return [[c for c in chars if c not in used]
+                    for chars in valid_next_row_chars]
+        return valid_next_row_chars
+
+    return Filter
+
+
+def RequiredLettersCellFilter(column_trie, letters):
+    """Return a cell_filter (see GenSubSquares) for squares that use all of
+      letters somewhere.
+
+      Every cell below the rows so far is in some column, so a letter the
+      rows haven't used yet has to be somewhere below one of the columns'
+      trie nodes, or the branch is dead. And if the cells left are only
+      just enough for the missing letters, they all have to be missing
+      letters. The finished squares still need checking (ColumnsInclude),
+      since the last row isn't filtered after it's placed.
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        missing = set(letters) - set("".join(rows))
+        if not missing:
+            return valid_next_row_chars
+        no_chars = [[] for _ in valid_next_row_chars]
+
+        below = set()
+        for column in TransposeSquare(rows):
+            node = column_trie.PrefixNode(column)
+            if node is None:
+                return no_chars
+            below |= node.LettersBelow()
+        if not missing <= below:
+            return no_chars
+
+        cells_left = ((column_trie.WordLen() - len(rows)) *
+                      len(valid_next_row_chars))
+        if len(missing) > cells_left:
+            return no_chars
+        if len(missing) == cells_left:
+            return [[c for c in chars if c in missing]
+                    for chars in valid_next_row_chars]
+        return valid_next_row_chars
+
+    return Filter
+
+
+def AnyLetterCellFilter(column_trie, letters):
+    """Return a cell_filter (see GenSubSquares) for squares that use at
+      least one of letters.
+
+      Until the rows use one, one of them has to be somewhere below one of
+      the columns' trie nodes, or the branch is dead. The finished squares
+      still need checking, since the last row isn't filtered after it's
+      placed.
+    """
+
+    letters = set(letters)
+
+    def Filter(rows, valid_next_row_chars):
+        if not letters.isdisjoint("".join(rows)):
+            return valid_next_row_chars
+        for column in TransposeSquare(rows):
+            node = column_trie.PrefixNode(column)
+            if node is None:
+                break
+            if not letters.isdisjoint(node.LettersBelow()):
+                return valid_next_row_chars
+        return [[] for _ in valid_next_row_chars]
+
+    return Filter
+
+
+def MustContainCellFilter(column_trie, word):
+    """Return a cell_filter (see GenSubSquares) for squares that have word
+      as a row or a column.
+
+      Until a row is word, some column has to still be heading for it, or
+      there has to be a row left that could be it. On the last row that's
+      down to the row itself or the columns one letter short, so if only one
+      of those is left, its cells are pinned to word's letters. The finished
+      squares still need checking, since the last row isn't filtered after
+      it's placed.
+    """
+
+    num_rows = column_trie.WordLen()
+
+    def Filter(rows, valid_next_row_chars):
+        num_cols = len(valid_next_row_chars)
+        if word in rows or (len(rows) < num_rows - 1 and
+                            len(word) == num_cols):
+            return valid_next_row_chars
+        columns = ["".join(column) for column in TransposeSquare(rows)]
+        heading_for_word = []
+        if len(word) == num_rows:
+            heading_for_word = [c for c, column in enumerate(columns)
+                                if word.startswith(column)]
+        if len(rows) < num_rows - 1:
+            if heading_for_word:
+                return valid_next_row_chars
+            return [[] for _ in valid_next_row_chars]
+
+        # The last row: it's word, or it finishes one of heading_for_word.
+        ways = [c for c in heading_for_word
+                if word[-1] in valid_next_row_chars[c]]
+        row_can_be_word = len(word) == num_cols and all(
+            [c in chars for c, chars in zip(word, valid_next_row_chars)])
+        if row_can_be_word and not ways:
+            return [[c] for c in word]
+        if len(ways) == 1 and not row_can_be_word:
+            return [[word[-1]] if c == ways[0] else chars
+                    for c, chars in enumerate(valid_next_row_chars)]
+        if not ways and not row_can_be_word:
+            return [[] for _ in valid_next_row_chars]
+        return valid_next_row_chars
+
+    return Filter
+
+
+def RankBudgetCellFilter(column_trie, word_ranks, max_total_rank):
+    """Return a cell_filter (see GenSubSquares) for squares whose row and
+      column words' frequency ranks add up to at most max_total_rank.
+
+      The rows so far count with their ranks, and each column with the
+      lowest rank it could still end up with, so a branch is dead once that
+      is over budget. The bottom rows aren't counted until they're placed,
+      so the finished squares still need checking.
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        total = sum([word_ranks[row] for row in rows])
+        for column in TransposeSquare(rows):
+            node = column_trie.PrefixNode(column)
+            if node is None:
+                return [[] for _ in valid_next_row_chars]
+            total += node.MinRank(word_ranks, column)
+        if total > max_total_rank:
+            return [[] for _ in valid_next_row_chars]
+        return valid_next_row_chars
+
+    return Filter
+
+
+def MaxLetterRepeatsCellFilter(max_repeats):
+    """Return a cell_filter (see GenSubSquares) for squares that use no
+      letter more than max_repeats times.
+
+      Letters that have used up their budget are taken out of the next
+      row. A row can still break the budget by using a letter twice, which
+      is caught when the row after it is filtered (or, for the last row, on
+      the finished square).
+    """
+
+    def Filter(rows, valid_next_row_chars):
+        counts = collections.Counter("".join(rows))
+        if counts and max(counts.values()) > max_repeats:
+            return [[] for _ in valid_next_row_chars]
+        return [[c for c in chars if counts[c] < max_repeats]
+                for chars in valid_next_row_chars]
+
+    return Filter
+
+
+def CombineCellFilters(cell_filters):
+    """Return one cell_filter that applies all of cell_filters, or None.
+    """
+
+    if not cell_filters:
+        return None
+    if len(cell_filters) == 1:
+        return cell_filters[0]
+
+    def Filter(rows, valid_next_row_chars):
+        for cell_filter in cell_filters:
+            valid_next_row_chars = cell_filter(rows, valid_next_row_chars)
+        return valid_next_row_chars
+
+    return Filter
+
+
+def GenNearMisses(word_trie, start_word, alphabet):
+    """Generate the grids with start_word at the top that *almost* work.
+
+      A near miss has valid words in every row and every column but one.
+      These are NOT valid squares; they show where a slightly bigger word list
+      would make more squares.
+
+      Args:
+        word_trie: WordTrie, the root
+        start_word: String of one word from the trie
+        alphabet: list of chars that can go in the unconstrained column
+
+      Returns:
+        A generator of (grid, bad_column), where bad_column is the index of
+        the column that isn't a word.
+    """
+
+    for bad_column in range(len(start_word)):
+        column_trie_nodes = []
+        for i, c in enumerate(start_word):
+            if i == bad_column:
+                column_trie_nodes.append(AnyCharNode(alphabet))
+            elif c in word_trie:
+                column_trie_nodes.append(word_trie.get(c))
+            else:
+                break
+        if len(column_trie_nodes) < len(start_word):
+            continue
+
+        for sq in GenSubSquares(word_trie, start_word, column_trie_nodes, []):
+            grid = [start_word] + sq
+            # Skip real squares, which have a word down bad_column too.
+            if word_trie.PrefixCount(TransposeSquare(grid)[bad_column]) == 0:
+                yield grid, bad_column
+
+
+def IsDoubleSquare(sq):
+    """ Figure out if this has columns that differ from the rows.
+
+      Rectangles (M rows of length N != M) always count as double.
+    """
+
+    return TransposeSquare(sq) != sq
+
+
+def WordsAreUnique(sq, is_double_square):
+    """Figure out if there are any shared words in rows + cols.
+
+    If not is_double_square, this'll look at just rows.
+    """
+
+    words = list(sq)
+    if is_double_square:
+        words += TransposeSquare(sq)
+    return len(set(words)) == len(words)
+
+
+def LongestSharedRowPrefix(sq):
+    """Return the length of the longest prefix shared by any two row words.
+    """
+
+    longest = 0
+    for i, word in enumerate(sq):
+        for other_word in sq[i + 1:]:
+            shared = 0
+            while shared < len(word) and word[shared] == other_word[shared]:
+                shared += 1
+            longest = max(longest, shared)
+    return longest
+
+
+def ColumnsInclude(sq, letters):
+    """Return True if every char in letters shows up in some column word.
+
+      Note that every cell is in exactly one row and one column, so for a
+      complete grid this is the same as the letters appearing anywhere in the
+      square, even for double squares whose columns differ from the rows.
+    """
+
+    column_chars = set("".join(TransposeSquare(sq)))
+    return all([c in column_chars for c in letters])
+
+
+def WordRanks(*word_lists):
+    """Return a dict of word -> index in its frequency-sorted word list.
+
+      Pass in both the row and column word lists when they're different.
+      A word in both keeps its rank from the first list it's in.
+    """
+
+    word_ranks = {}
+    for word_list in word_lists:
+        for i, word in enumerate(word_list):
+            word_ranks.setdefault(word, i)
+    return word_ranks
+
+
+def FrequencyScore(sq, word_ranks):
+    """Score a square by how common its words are.
+
+      The score is the mean frequency rank (0 is the most common word) over
+      all the row and column words, so lower scores mean more common words.
+
+      Args:
+        sq: list of N words
+        word_ranks: dict of word -> index in the frequency-sorted word list
+    """
+
+    words = sq + TransposeSquare(sq)
+    return sum([word_ranks[word] for word in words]) / len(words)
+
+
+SCRABBLE_LETTER_SCORES = {
+    "a": 1, "b": 3, "c": 3, "d": 2, "e": 1, "f": 4, "g": 2, "h": 4, "i": 1,
+    "j": 8, "k": 5, "l": 1, "m": 3, "n": 1, "o": 1, "p": 3, "q": 10, "r": 1,
+    "s": 1, "t": 1, "u": 1, "v": 4, "w": 4, "x": 8, "y": 4, "z": 10}
+
+SCORE_MODES = ["frequency", "scrabble"]
+
+
+def ScrabbleScore(sq):
+    """Score a square by the Scrabble tile values of its letters.
+
+      Each cell counts once, not once for its row and again for its column.
+      Letters without a tile (from a custom --alphabet) score 0.
+    """
+
+    return sum([SCRABBLE_LETTER_SCORES.get(c, 0) for c in "".join(sq)])
+
+
+def SquareScore(sq, word_ranks, score_mode="frequency"):
+    """Score a square with FrequencyScore or ScrabbleScore.
+
+      Args:
+        score_mode: one of SCORE_MODES
+    """
+
+    if score_mode == "scrabble":
+        return ScrabbleScore(sq)
+    return FrequencyScore(sq, word_ranks)
+
+
+def FormatScore(score):
+    """Format a SquareScore: whole tile scores, and ranks to 1 decimal."""
+
+    if isinstance(score, int):
+        return "score %d" % score
+    return "score %.1f" % score
+
+
+class DifficultyRater:
+    """Rates how hard a square would be to solve as a puzzle, from 0 to 100.
+
+      The rating is the mean of three parts, each from 0 to 1:
+        - how obscure the words are: their mean frequency rank, as a
+          fraction of the word list
+        - how much the letters repeat: the fraction of cells holding a
+          letter used in an earlier cell
+        - how unusual the letter pairs are: for each pair of neighbouring
+          letters in a row or column, the fraction of all the letter pairs
+          in the word list that are more common than it
+    """
+
+    # Most squares rate 35 to 55, so these split a typical run into rough
+    # thirds, rather than splitting 0 to 100 evenly.
+    LEVELS = [(40, "easy"), (50, "medium"), (100, "hard")]
+
+    def __init__(self, *word_lists):
+        """
+          Args:
+            word_lists: the frequency-sorted word lists the squares use
+        """
+
+        self._word_ranks = WordRanks(*word_lists)
+        self._num_words = max([len(word_list) for word_list in word_lists])
+        bigram_counts = collections.Counter()
+        for word in self._word_ranks:
+            bigram_counts.update(zip(word, word[1:]))
+        total = sum(bigram_counts.values())
+        self._bigram_rarity = {}
+        more_common = 0
+        for bigram, count in bigram_counts.most_common():
+            self._bigram_rarity[bigram] = more_common / total
+            more_common += count
+
+    def Rate(self, sq):
+        """Return the difficulty of sq, from 0 (easiest) to 100."""
+
+        words = sq + TransposeSquare(sq)
+        obscurity = (sum([self._word_ranks[word] for word in words]) /
+                     len(words) / self._num_words)
+        letters = "".join(sq)
+        repeats = 1 - len(set(letters)) / len(letters)
+        bigrams = [bigram for word in words for bigram in zip(word, word[1:])]
+        rarity = (sum([self._bigram_rarity.get(bigram, 1)
+                       for bigram in bigrams]) / len(bigrams))
+        return 100 * (obscurity + repeats + rarity) / 3
+
+    @classmethod
+    def Level(cls, difficulty):
+        """Return "easy", "medium" or "hard" for a Rate difficulty."""
+
+        for top, level in cls.LEVELS:
+            if difficulty <= top:
+                return level
+        return cls.LEVELS[-1][1]
+
+
+def CountThemeWords(sq, theme_words):
+    """Return how many of the row and column words are in theme_words.
+
+      A word counts each time it's used, so a single square's words count
+      twice (once as a row, once as a column).
+    """
+
+    return len([word for word in sq + TransposeSquare(sq)
+                if word in theme_words])
+
+
+def TotalRank(sq, word_ranks):
+    """Return the sum of the frequency ranks of the row and column words."""
+
+    return sum([word_ranks[word] for word in sq + TransposeSquare(sq)])
+
+
+def TransposeSquare(sq):
+    """Return the square with its rows and columns swapped.
+    """
+
+    return ["".join([word[i] for word in sq]) for i in range(len(sq[0]))]
+
+
+def CanonicalKey(sq, transposable=True):
+    """Return a string key that's the same for a square and its transpose.
+
+      A rectangle's transpose is a different shape, so it's never a repeat
+      of the rectangle; its key is just its rows. So is the key of a square
+      whose rows and columns come from different word lists (transposable
+      False), since its transpose has column words as its rows.
+    """
+
+    if not transposable or len(sq) != len(sq[0]):
+        return "/".join(sq)
+    return min("/".join(sq), "/".join(TransposeSquare(sq)))
+
+
+class RecentSquares:
+    """Remembers the canonical keys of the last N squares seen, as an LRU.
+
+      Exact dedup would have to remember every square forever. This keeps
+      memory bounded at N keys, at the cost of only catching repeats that
+      show up within N squares of each other: far-apart duplicates slip
+      through and get printed twice.
+    """
+
+    def __init__(self, window_size, transposable=True):
+        """
+          Args:
+            window_size: how many squares to remember
+            transposable: as for CanonicalKey
+        """
+        self._window_size = window_size
+        self._transposable = transposable
+        self._keys = collections.OrderedDict()
+
+    def SeenRecently(self, sq):
+        """Return True if sq (or its transpose) is in the window.
+
+          Either way, sq becomes the most recently seen entry.
+        """
+        key = CanonicalKey(sq, self._transposable)
+        if key in self._keys:
+            self._keys.move_to_end(key)
+            return True
+
+        self._keys[key] = True
+        if len(self._keys) > self._window_size:
+            self._keys.popitem(last=False)
+        return False
+
+
+def DoSomeBenchmarking(working_words, word_trie, rng, seconds=10):
+    """Print out some benchmarks at how fast we can make squares
+
+      This times building the trie (plain and as a DAWG), making words from
+      it, and how many squares a second each search makes, so changes to
+      any of them can be compared on the same word list.
+
+      Args:
+        working_words: list of words to use
+        word_trie: root node of WordTrie
+        rng: random.Random used to shuffle the start words. Pass in one
+          with a fixed seed to get a repeatable run.
+        seconds: how long to run each timed search for
+    """
+
+    start_words = list(working_words)
+    rng.shuffle(start_words)
+
+    start_time = time.time()
+    plain_trie = WordTrie(working_words)
+    dur = time.time() - start_time
+    print("Built the trie of %d words in %.3f sec, %.1f MB" % (
+        len(working_words), dur, TrieBytes(plain_trie) / 1e6))
+    dawg = WordTrie(working_words)
+    start_time = time.time()
+    dawg.Minimize()
+    dur = time.time() - start_time
+    print("Made it a DAWG in %.3f sec more, %.1f MB" % (
+        dur, TrieBytes(dawg) / 1e6))
+
+    all_chars = [WordAlphabet(working_words)] * word_trie.WordLen()
+    start_time = time.time()
+    words = 0
+    while time.time() < start_time + seconds:
+        for _ in GenWordsFromValidChars(word_trie, all_chars):
+            words += 1
+    dur = time.time() - start_time
+    print("Made %d words in %.1f sec, or %.0f words/sec" % (words, dur,
+          words / dur))
+
+    word_index = WordIndex(working_words)
+    searches = [
+        ("rows", lambda start_word: GenSquares(word_trie, start_word)),
+        ("rows, DAWG", lambda start_word: GenSquares(dawg, start_word)),
+        ("order_by_branching", lambda start_word: GenSquares(
+            word_trie, start_word, order_by_branching=True)),
+        ("single_squares_only", lambda start_word: GenSquares(
+            word_trie, start_word, symmetric_only=True)),
+        ("most_constrained", lambda start_word: GenSquares(
+            word_trie, start_word, word_indexes=(word_index, word_index))),
+    ]
+    for name, gen_squares in searches:
+        start_time = time.time()
+        squares = 0
+        pos = 0
+        target_time = start_time + seconds
+        while time.time() < target_time:
+            for sq in gen_squares(start_words[pos]):
+                squares += 1
+
+            pos += 1
+            if pos >= len(start_words):
+                pos = 0
+
+        dur = time.time() - start_time
+        print("%s: ran %d squares in %.1f sec, or %.0f squares/sec" % (
+            name, squares, dur, squares / dur))
+
+
+def EstimateSearch(working_words, word_trie, rng, num_probes,
+                   column_trie=None):
+    """Estimate how big the plain exhaustive search is, without doing it.
+
+      This is Knuth's estimator. Each probe walks one random path down the
+      search tree from a random start word, multiplying up the number of
+      choices at each level, and that product is an unbiased guess at the
+      number of nodes at that depth. We average over the probes. Weighting
+      the time each step takes to find its next rows the same way estimates
+      the run time.
+
+      Args:
+        working_words: list of start words
+        word_trie: WordTrie of the row words
+        rng: random.Random to pick the paths
+        num_probes: how many paths to walk. More is slower but steadier.
+        column_trie: Optional WordTrie of the column words, for rectangles
+
+      Returns:
+        (nodes, squares, seconds), the estimated partial squares visited,
+        squares made and run time of the whole search
+    """
+
+    column_trie = column_trie or word_trie
+    total_nodes = 0
+    total_squares = 0
+    total_seconds = 0
+    for _ in range(num_probes):
+        weight = len(working_words)
+        start_word = rng.choice(working_words)
+        column_trie_nodes = [column_trie.get(c) for c in start_word]
+        while None not in column_trie_nodes:
+            total_nodes += weight
+            if column_trie_nodes[0] == True:
+                total_squares += weight
+                break
+            step_start_time = time.perf_counter()
+            next_rows = list(GenWordsFromValidChars(
+                word_trie, [sorted(node.keys()) for node in column_trie_nodes]))
+            total_seconds += weight * (time.perf_counter() - step_start_time)
+            if not next_rows:
+                break
+            weight *= len(next_rows)
+            row = rng.choice(next_rows)
+            column_trie_nodes = [node[c] for node, c in
+                                 zip(column_trie_nodes, row)]
+
+    return (total_nodes / num_probes, total_squares / num_probes,
+            total_seconds / num_probes)
+
+
+def TrieBytes(word_trie, seen=None):
+    """Return roughly how much memory a WordTrie takes, in bytes.
+
+      Nodes shared after WordTrie.Minimize are only counted once.
+    """
+
+    if seen is None:
+        seen = set()
+    if id(word_trie) in seen:
+        return 0
+    seen.add(id(word_trie))
+    return sys.getsizeof(word_trie) + sum(
+        [TrieBytes(node, seen) for node in word_trie.values() if node != True])
+
+
+def TrieNodeCount(word_trie, seen=None):
+    """Return how many nodes a WordTrie has, counting shared ones once."""
+
+    if seen is None:
+        seen = set()
+    if id(word_trie) in seen:
+        return 0
+    seen.add(id(word_trie))
+    return 1 + sum([TrieNodeCount(node, seen) for node in word_trie.values()
+                    if node != True])
+
+
+def PeakRssBytes():
+    """Return the most memory this process has had resident, in bytes, or
+      None where there's no resource module to ask (Windows).
+    """
+
+    try:
+        import resource
+    except ImportError:
+        return None
+    max_rss = resource.getrusage(resource.RUSAGE_SELF).ru_maxrss
+    # Linux gives kilobytes, macOS bytes.
+    return max_rss if sys.platform == "darwin" else max_rss * 1024
+
+
+def FormatRss(rss_bytes):
+    """Return PeakRssBytes' result as a string of MB."""
+
+    if rss_bytes is None:
+        return "unknown on this platform"
+    return "%.1f MB" % (rss_bytes / 1e6)
+
+
+def PrintMemoryUsage(working_words, word_trie, column_words=None,
+                     column_trie=None):
+    """Print how much memory the word lists and tries take, and the peak RSS.
+
+      Args:
+        working_words, word_trie: the row words and their trie
+        column_words, column_trie: Optional separate column ones
+    """
+
+    print("Memory used:")
+    for name, trie in [("row trie", word_trie), ("column trie", column_trie)]:
+        if trie is not None:
+            nodes = TrieNodeCount(trie)
+            trie_bytes = TrieBytes(trie)
+            print("  %s: %d nodes x %.0f bytes = %.1f MB" % (
+                name, nodes, trie_bytes / nodes, trie_bytes / 1e6))
+    for name, words in [("row words", working_words),
+                        ("column words", column_words)]:
+        if words is not None:
+            print("  %s: %d words, %.1f MB" % (
+                name, len(words), (sys.getsizeof(words) +
+                                   sum(map(sys.getsizeof, words))) / 1e6))
+    print("  peak RSS so far: %s\n" % FormatRss(PeakRssBytes()))
+
+
+class Config:
+    """All the settings for generating squares.
+
+      This is separate from the argparse args, so the generator can be used
+      without the command line.
+
+      Attributes:
+        fill_order: "rows" or "columns", see GenSquares
+        per_first_row_timeout_ms: Optional time budget for each start word
+        order_by_branching: if True, try the most promising letters first
+        order_by_letter_frequency: if True, try the letters used most in the
+          word lists first
+        double_squares_only: only keep unique double squares, and don't
+          search for others
+        single_squares_only: only keep single (symmetric) squares
+        score_mode: one of SCORE_MODES, for which SquareScore to use
+        min_score, max_score: Optional SquareScore band to keep
+        min_difficulty, max_difficulty: Optional DifficultyRater band to
+          keep
+        max_shared_prefix: Optional limit on prefixes shared by two rows
+        column_must_include: string of letters the square must contain
+        rare_letters: string of letters the square must contain at least
+          one of, or "" for no limit
+        must_contain: Optional word that has to be one of the rows or columns
+        dedup_window: if > 0, skip squares seen within this many squares
+        dedupe_transpose: skip every square whose transpose came out before,
+          and give each square as whichever of it and its transpose comes
+          first alphabetically
+        diagonal_word: the main diagonal has to be a word too
+        anti_diagonal_word: the other diagonal has to be a word too
+        palindromes: None, "rows" for squares where every row is a
+          palindrome, or "rotational" for squares that look the same
+          turned 180 degrees
+        propagate: if True, narrow down each cell's letters with
+          PropagateCellDomains before searching each start word
+        row_order: "top_down" to fill rows in order, or "most_constrained"
+          to fill the row with the fewest possible words next
+        algorithm: "exhaustive" to find every square, or "beam" for a
+          quick GenBeamSquares search
+        beam_width: how many partial squares the beam search keeps
+        anneal_restarts, anneal_steps: for the "anneal" algorithm, see
+          GenAnnealedSquares
+        sample: if > 0, just make this many random squares, with
+          GenSampledSquares
+        sample_walks: how many random walks to do when sampling
+        rng: random.Random for the "anneal" algorithm and sampling
+        deepening_step: if > 0, search the top deepening_step words first,
+          then twice that many, and so on, so squares of common words come
+          out first
+        start_word: Optional word to use as the only start word
+        row_patterns: Optional dict of row number (from 0) -> pattern that
+          row has to fit, with "." for any letter
+        banned_cells: Optional list per row of the set of letters each cell
+          can't have
+        min_distinct_letters: only keep squares using at least this many
+          different letters
+        max_letter_repeats: if > 0, only keep squares that use no letter
+          more than this many times
+        max_total_rank: Optional limit on the TotalRank of a square
+        theme_words, min_theme_words: only keep squares using at least
+          min_theme_words of the words in the theme_words set
+        shuffle: if True, go through the start words in a random order
+          from rng, rather than most popular first
+        first_position: skip this many start words (in the order they're
+          searched), to resume a run
+        on_start_word: Optional function(position) called as the search
+          starts on each start word, with how many came before it, to track
+          progress
+        shard: Optional (i, n), to only search every n'th start word,
+          starting from word i - 1 (i goes from 1 to n)
+        deadline: Optional time.time() value to stop the search at
+    """
+
+    def __init__(self, fill_order="rows", per_first_row_timeout_ms=None,
+                 order_by_branching=False, order_by_letter_frequency=False,
+                 double_squares_only=False,
+                 single_squares_only=False,
+                 score_mode="frequency", min_score=None, max_score=None,
+                 min_difficulty=None, max_difficulty=None,
+                 max_shared_prefix=None,
+                 column_must_include="", rare_letters="", must_contain=None,
+                 dedup_window=0,
+                 dedupe_transpose=False, diagonal_word=False,
+                 anti_diagonal_word=False, palindromes=None, propagate=False,
+                 row_order="top_down", algorithm="exhaustive", beam_width=100,
+                 anneal_restarts=50, anneal_steps=100000, sample=0,
+                 sample_walks=10000, rng=None, deepening_step=0,
+                 start_word=None, row_patterns=None, banned_cells=None,
+                 min_distinct_letters=0,
+                 max_letter_repeats=0, max_total_rank=None, theme_words=None,
+                 min_theme_words=0, shuffle=False,
+                 first_position=0,
+                 on_start_word=None,
+                 shard=None,
+                 deadline=None):
+        self.fill_order = fill_order
+        self.per_first_row_timeout_ms = per_first_row_timeout_ms
+        self.order_by_branching = order_by_branching
+        self.order_by_letter_frequency = order_by_letter_frequency
+        self.double_squares_only = double_squares_only
+        self.single_squares_only = single_squares_only
+        self.score_mode = score_mode
+        self.min_difficulty = min_difficulty
+        self.max_difficulty = max_difficulty
+        self.min_score = min_score
+        self.max_score = max_score
+        self.max_shared_prefix = max_shared_prefix
+        self.column_must_include = column_must_include
+        self.rare_letters = rare_letters
+        self.must_contain = must_contain
+        self.dedup_window = dedup_window
+        self.dedupe_transpose = dedupe_transpose
+        self.diagonal_word = diagonal_word
+        self.anti_diagonal_word = anti_diagonal_word
+        self.palindromes = palindromes
+        self.propagate = propagate
+        self.row_order = row_order
+        self.algorithm = algorithm
+        self.beam_width = beam_width
+        self.anneal_restarts = anneal_restarts
+        self.anneal_steps = anneal_steps
+        self.sample = sample
+        self.sample_walks = sample_walks
+        self.rng = rng if rng is not None else random.Random()
+        self.deepening_step = deepening_step
+        self.start_word = start_word
+        self.row_patterns = row_patterns
+        self.banned_cells = banned_cells
+        self.min_distinct_letters = min_distinct_letters
+        self.max_letter_repeats = max_letter_repeats
+        self.max_total_rank = max_total_rank
+        self.theme_words = theme_words or set()
+        self.min_theme_words = min_theme_words
+        self.shuffle = shuffle
+        self.first_position = first_position
+        self.on_start_word = on_start_word
+        self.shard = shard
+        self.deadline = deadline
+
+
+def GenFilteredSquares(working_words, word_trie, config, column_words=None,
+                       column_trie=None):
+    """Generate all the squares from all the start words that pass the filters.
+
+      Args:
+        working_words: list of words to use, most popular first
+        word_trie: root node of WordTrie
+        config: Config with the search and filter settings
+        column_words, column_trie: Optional separate column words, most
+          popular first, and their WordTrie, for making rectangles
+
+      Returns:
+        A generator of (word_num, sq, is_double_square, words_are_unique),
+        where word_num is the index of sq's start word in working_words.
+    """
+
+    if config.deepening_step > 0:
+        yield from GenDeepeningSquares(working_words, config, column_words)
+        return
+
+    word_ranks = WordRanks(working_words, column_words or [])
+    difficulty_rater = None
+    if config.min_difficulty is not None or config.max_difficulty is not None:
+        difficulty_rater = DifficultyRater(working_words, column_words or [])
+
+    recent_squares = None
+    if config.dedup_window > 0:
+        recent_squares = RecentSquares(config.dedup_window,
+                                       column_words is None)
+    seen_keys = set()
+
+    cell_filters = []
+    if config.diagonal_word or config.anti_diagonal_word:
+        cell_filters.append(DiagonalCellFilter(
+            word_trie, config.diagonal_word, config.anti_diagonal_word))
+    if config.palindromes == "rotational":
+        cell_filters.append(RotationalCellFilter)
+    if config.row_patterns:
+        cell_filters.append(PatternCellFilter(config.row_patterns))
+    if config.banned_cells:
+        cell_filters.append(BannedLettersCellFilter(config.banned_cells))
+    if config.min_distinct_letters:
+        cell_filters.append(MinDistinctLettersCellFilter(
+            column_trie or word_trie, config.min_distinct_letters))
+    if config.column_must_include:
+        cell_filters.append(RequiredLettersCellFilter(
+            column_trie or word_trie, config.column_must_include))
+    if config.rare_letters:
+        cell_filters.append(AnyLetterCellFilter(
+            column_trie or word_trie, config.rare_letters))
+    if config.must_contain:
+        cell_filters.append(MustContainCellFilter(
+            column_trie or word_trie, config.must_contain))
+    if config.max_total_rank is not None:
+        cell_filters.append(RankBudgetCellFilter(
+            column_trie or word_trie, word_ranks, config.max_total_rank))
+    if config.max_letter_repeats:
+        cell_filters.append(MaxLetterRepeatsCellFilter(
+            config.max_letter_repeats))
+    cell_filter = CombineCellFilters(cell_filters)
+    top_pattern = (config.row_patterns or {}).get(0)
+    top_bans = config.banned_cells[0] if config.banned_cells else None
+
+    # For palindromic rows, only search rows from the palindromes, but
+    # columns can be any word.
+    row_trie = word_trie
+    if config.palindromes == "rows":
+        row_trie = WordTrie([word for word in working_words
+                             if word == word[::-1]])
+        column_trie = word_trie
+
+    letter_ranks = None
+    if config.order_by_letter_frequency:
+        letter_ranks = LetterRanks(working_words + (column_words or []))
+
+    word_indexes = None
+    if config.row_order == "most_constrained":
+        word_indexes = (WordIndex(working_words),
+                        WordIndex(column_words or working_words))
+
+    def GenCandidateSquares():
+        """Generate (word_num, sq) for all the squares, before filtering."""
+        start_words = working_words
+        if config.palindromes == "rows":
+            start_words = [word for word in working_words
+                           if word == word[::-1]]
+        if top_pattern is not None:
+            start_words = [word for word in start_words
+                           if MatchesPattern(word, top_pattern)]
+        if top_bans is not None:
+            start_words = [word for word in start_words
+                           if AvoidsBannedLetters(word, top_bans)]
+
+        if config.sample > 0:
+            for sq in GenSampledSquares(row_trie, column_trie or row_trie,
+                                        start_words, config.rng, config.sample,
+                                        config.sample_walks, cell_filter):
+                if config.fill_order == "columns":
+                    sq = TransposeSquare(sq)
+                yield word_ranks[sq[0]], sq
+            return
+
+        if config.algorithm == "beam":
+            for sq in GenBeamSquares(row_trie, column_trie or row_trie,
+                                     start_words, config.beam_width,
+                                     word_ranks, cell_filter):
+                if config.fill_order == "columns":
+                    sq = TransposeSquare(sq)
+                yield word_ranks[sq[0]], sq
+            return
+
+        if config.algorithm == "sat":
+            for sq in GenSatSquares(start_words, column_words or working_words):
+                if config.fill_order == "columns":
+                    sq = TransposeSquare(sq)
+                yield word_ranks[sq[0]], sq
+            return
+
+        if config.algorithm == "anneal":
+            for sq in GenAnnealedSquares(start_words, column_trie or row_trie,
+                                         config.rng, config.anneal_restarts,
+                                         config.anneal_steps):
+                if config.fill_order == "columns":
+                    sq = TransposeSquare(sq)
+                yield word_ranks[sq[0]], sq
+            return
+
+        search_order = list(enumerate(working_words))
+        if config.shuffle:
+            config.rng.shuffle(search_order)
+        for position, (word_num, start_word) in enumerate(search_order):
+            if position < config.first_position:
+                continue
+            if config.start_word is not None and start_word != config.start_word:
+                continue
+            if (top_pattern is not None and
+                    not MatchesPattern(start_word, top_pattern)):
+                continue
+            if (top_bans is not None and
+                    not AvoidsBannedLetters(start_word, top_bans)):
+                continue
+            if (config.shard is not None and
+                    word_num % config.shard[1] != config.shard[0] - 1):
+                continue
+            if config.deadline is not None and time.time() > config.deadline:
+                return
+            if config.on_start_word is not None:
+                config.on_start_word(position)
+            if config.palindromes == "rows" and start_word != start_word[::-1]:
+                continue
+            start_word_cell_filter = cell_filter
+            if config.propagate:
+                domains = PropagateCellDomains(row_trie,
+                                               column_trie or row_trie,
+                                               start_word)
+                if domains is None:
+                    continue
+                start_word_cell_filter = CombineCellFilters(
+                    cell_filters + [DomainCellFilter(domains)])
+            for sq in GenSquares(row_trie, start_word, config.fill_order,
+                                 config.per_first_row_timeout_ms,
+                                 config.order_by_branching,
+                                 config.single_squares_only, column_trie,
+                                 start_word_cell_filter, word_indexes,
+                                 config.deadline, config.double_squares_only,
+                                 letter_ranks):
+                yield word_num, sq
+
+    for word_num, sq in GenCandidateSquares():
+        if SEARCH_STATS is not None:
+            SEARCH_STATS["squares before the filters"] += 1
+        # Collect some classifications of this sq.
+        is_double_square = IsDoubleSquare(sq)
+        words_are_unique = WordsAreUnique(sq, is_double_square)
+        if config.double_squares_only and (not is_double_square
+                                           or not words_are_unique):
+            continue
+        if config.single_squares_only and is_double_square:
+            continue
+        if not ColumnsInclude(sq, config.column_must_include):
+            continue
+        if (config.rare_letters and
+                set(config.rare_letters).isdisjoint("".join(sq))):
+            continue
+        if (config.must_contain and config.must_contain not in sq and
+                config.must_contain not in TransposeSquare(sq)):
+            continue
+        if len(set("".join(sq))) < config.min_distinct_letters:
+            continue
+        if (config.theme_words and
+                CountThemeWords(sq, config.theme_words) <
+                config.min_theme_words):
+            continue
+        if (config.max_total_rank is not None and
+                TotalRank(sq, word_ranks) > config.max_total_rank):
+            continue
+        if (config.max_letter_repeats and
+                max(collections.Counter("".join(sq)).values()) >
+                config.max_letter_repeats):
+            continue
+        if (config.max_shared_prefix is not None and
+                LongestSharedRowPrefix(sq) > config.max_shared_prefix):
+            continue
+        if config.min_score is not None or config.max_score is not None:
+            score = SquareScore(sq, word_ranks, config.score_mode)
+            if config.min_score is not None and score < config.min_score:
+                continue
+            if config.max_score is not None and score > config.max_score:
+                continue
+        if difficulty_rater is not None:
+            difficulty = difficulty_rater.Rate(sq)
+            if (config.min_difficulty is not None and
+                    difficulty < config.min_difficulty):
+                continue
+            if (config.max_difficulty is not None and
+                    difficulty > config.max_difficulty):
+                continue
+        if recent_squares and recent_squares.SeenRecently(sq):
+            continue
+        if config.dedupe_transpose:
+            key = CanonicalKey(sq, column_words is None)
+            if key in seen_keys:
+                continue
+            seen_keys.add(key)
+            if key != "/".join(sq):
+                sq = key.split("/")
+                word_num = word_ranks[sq[0]]
+        yield word_num, sq, is_double_square, words_are_unique
+
+
+def GenDeepeningSquares(working_words, config, column_words=None):
+    """Generate the squares from bigger and bigger slices of the word lists.
+
+      The first pass only uses the config.deepening_step most popular words,
+      the next twice as many, and so on up to all of them. Each pass skips
+      the squares earlier passes already made, so the squares made of the
+      most common words come out first, each just once.
+
+      Args:
+        working_words, column_words: as for GenFilteredSquares
+        config: Config, with deepening_step > 0
+
+      Returns:
+        A generator like GenFilteredSquares
+    """
+
+    pass_config = copy.copy(config)
+    pass_config.deepening_step = 0
+    seen = set()
+    cutoff = 0
+    while cutoff < max(len(working_words), len(column_words or [])):
+        cutoff += config.deepening_step
+        pass_words = working_words[:cutoff]
+        pass_column_words = None
+        pass_column_trie = None
+        if column_words is not None:
+            pass_column_words = column_words[:cutoff]
+            pass_column_trie = WordTrie(pass_column_words)
+        print("Searching the top %d words\n" %
+              min(cutoff, max(len(working_words), len(column_words or []))))
+        for result in GenFilteredSquares(pass_words, WordTrie(pass_words),
+                                         pass_config, pass_column_words,
+                                         pass_column_trie):
+            key = "/".join(result[1])
+            if key not in seen:
+                seen.add(key)
+                yield result
+
+
+def GenParallelSquares(working_words, word_trie, config, num_workers,
+                       column_words=None, column_trie=None, batch_size=100):
+    """Generate the squares with num_workers processes searching at once.
+
+      Worker i searches shard i of the start words, like --shard, and sends
+      its squares back in batches. The processes are forked, so they share
+      the tries with this one rather than each building their own.
+
+      The squares come out in the same order as from GenFilteredSquares.
+      Each batch says which start word (by position in the search order)
+      its worker is on, and a worker only moves forward, so once every
+      worker is past a start word, all its squares are in. Squares are
+      held back until then, so a slow start word holds up the output, but
+      not the workers.
+
+      Args:
+        working_words, word_trie, config, column_words, column_trie: as for
+          GenFilteredSquares. config.shard has to be None.
+        num_workers: how many processes to search with
+        batch_size: how many squares a worker sends back at a time
+
+      Returns:
+        A generator like GenFilteredSquares
+    """
+
+    context = multiprocessing.get_context("fork")
+    results = context.Queue()
+
+    def Work(worker_num):
+        worker_config = copy.copy(config)
+        worker_config.shard = (worker_num + 1, num_workers)
+        position = -1
+        batch = []
+
+        def OnStartWord(next_position):
+            nonlocal position, batch
+            results.put((worker_num, position, batch))
+            position = next_position
+            batch = []
+        worker_config.on_start_word = OnStartWord
+
+        try:
+            for result in GenFilteredSquares(working_words, word_trie,
+                                             worker_config, column_words,
+                                             column_trie):
+                batch.append((position, result))
+                if len(batch) >= batch_size:
+                    results.put((worker_num, position, batch))
+                    batch = []
+        except Exception:
+            traceback.print_exc()
+            sys.stderr.flush()
+            # None says this worker failed.
+            results.put(None)
+            return
+        results.put((worker_num, position, batch))
+        # And this says it's done.
+        results.put((worker_num, math.inf, []))
+
+    workers = [context.Process(target=Work, args=(worker_num,), daemon=True)
+               for worker_num in range(num_workers)]
+    # Otherwise anything still buffered gets printed again by each worker.
+    sys.stdout.flush()
+    for worker in workers:
+        worker.start()
+    try:
+        # The start word each worker is on, and the squares waiting for all
+        # the workers to get past theirs, as (position, arrival, result).
+        worker_positions = [-1] * num_workers
+        waiting = []
+        num_arrived = 0
+        while min(worker_positions) < math.inf:
+            message = results.get()
+            if message is None:
+                raise RuntimeError("A --threads worker failed")
+            worker_num, position, batch = message
+            worker_positions[worker_num] = position
+            if (config.on_start_word is not None and
+                    min(worker_positions) < math.inf):
+                # Every start word before this one is done.
+                config.on_start_word(min(worker_positions))
+            for result_position, result in batch:
+                heapq.heappush(waiting, (result_position, num_arrived, result))
+                num_arrived += 1
+            while waiting and waiting[0][0] < min(worker_positions):
+                yield heapq.heappop(waiting)[2]
+    finally:
+        for worker in workers:
+            worker.terminate()
+            worker.join()
+
+
+def FormatSquareWithCoords(sq):
+    """Return the square as text with row and column numbers around the edges.
+
+      This is for debugging, so that "column 3" in a message can be found by
+      eye. Example:
+
+             0 1 2 3
+           0 s l o w
+           1 l o v e
+           2 o v e r
+           3 w e r e
+    """
+
+    width = len(str(max(len(sq), len(sq[0])) - 1))
+    lines = [" " * (width + 1) + " ".join(["%*d" % (width, i)
+                                           for i in range(len(sq[0]))])]
+    for i, word in enumerate(sq):
+        lines.append("%*d " % (width, i) +
+                     " ".join(["%*s" % (width, c) for c in word]))
+    return "\n".join(lines)
+
+
+def FormatSquare(sq, sq_num, word_num, num_words, is_double_square,
+                 words_are_unique, show_coords=False, score=None,
+                 difficulty=None, proper_nouns=frozenset()):
+    """Return the text we print for one square, including the blank line after.
+
+      Args:
+        score: Optional SquareScore to print in the WordSquare line
+        difficulty: Optional DifficultyRater rating to print there too
+        proper_nouns: set of words to list there as proper nouns, if
+          they're in the square
+    """
+
+    desc = "WordSquare %d from word %d/%d: %s-word-square, %s" % (
+        sq_num, word_num, num_words,
+        "double" if is_double_square else "single",
+        "unique" if words_are_unique else "non-unique")
+    if score is not None:
+        desc += ", " + FormatScore(score)
+    if difficulty is not None:
+        desc += ", difficulty %.0f (%s)" % (difficulty,
+                                            DifficultyRater.Level(difficulty))
+    if proper_nouns:
+        names = [word for word in sq + TransposeSquare(sq)
+                 if word in proper_nouns]
+        if names:
+            desc += ", proper nouns: " + ", ".join(
+                sorted(set(names), key=names.index))
+    if show_coords:
+        rows = ["  " + line for line in FormatSquareWithCoords(sq).split("\n")]
+    else:
+        rows = ["  " + word for word in sq]
+    return "\n".join([desc] + rows) + "\n"
+
+
+def PrintFirstLetterHistogram(first_letter_counts):
+    """Print how many squares there were for each first letter, as a histogram.
+
+      Args:
+        first_letter_counts: collections.Counter of first letter -> squares
+    """
+
+    print("\nSquares by first letter:")
+    most = max(first_letter_counts.values(), default=0)
+    for letter in sorted(first_letter_counts):
+        count = first_letter_counts[letter]
+        print("  %s %6d %s" % (letter, count, "#" * round(50 * count / most)))
+
+
+def SquareToSvg(sq, x, y, tile_size, label=None):
+    """Return SVG elements drawing one square as a grid of letter cells.
+
+      Args:
+        sq: list of N words
+        x, y: top left corner, in pixels
+        tile_size: width and height of the whole grid, in pixels
+        label: Optional text to put under the grid
+      Returns:
+        String of SVG elements (no <svg> wrapper), so they can be placed in
+        a bigger picture.
+    """
+
+    cell_size = tile_size / max(len(sq), len(sq[0]))
+    elements = ['<g font-family="sans-serif" font-size="%.1f" '
+                'text-anchor="middle">' % (cell_size * 0.6)]
+    for row, word in enumerate(sq):
+        for col, c in enumerate(word):
+            cell_x = x + col * cell_size
+            cell_y = y + row * cell_size
+            elements.append('<rect x="%.1f" y="%.1f" width="%.1f" height="%.1f" '
+                            'fill="white" stroke="black"/>' %
+                            (cell_x, cell_y, cell_size, cell_size))
+            elements.append('<text x="%.1f" y="%.1f">%s</text>' %
+                            (cell_x + cell_size / 2, cell_y + cell_size * 0.75,
+                             c.upper()))
+    if label is not None:
+        elements.append('<text x="%.1f" y="%.1f" font-size="12">%s</text>' %
+                        (x + tile_size / 2, y + tile_size + 15, label))
+    elements.append('</g>')
+    return "\n".join(elements)
+
+
+def WriteContactSheet(filename, squares_and_labels, columns, tile_size):
+    """Write an SVG file laying out many squares as tiles on one page.
+
+      Args:
+        filename: SVG file to write
+        squares_and_labels: list of (sq, label string) to draw, in order
+        columns: how many tiles to put in each row of the page
+        tile_size: size of each square's grid, in pixels
+    """
+
+    margin = 20
+    label_height = 20
+    tile_width = tile_size + margin
+    tile_height = tile_size + label_height + margin
+    page_rows = (len(squares_and_labels) + columns - 1) // columns
+
+    tiles = []
+    for i, (sq, label) in enumerate(squares_and_labels):
+        tiles.append(SquareToSvg(sq, margin + (i % columns) * tile_width,
+                                 margin + (i // columns) * tile_height,
+                                 tile_size, label))
+
+    with open(filename, 'w') as f:
+        f.write('<svg xmlns="http://www.w3.org/2000/svg" width="%d" height="%d">\n'
+                % (margin + columns * tile_width, margin + page_rows * tile_height))
+        f.write("\n".join(tiles))
+        f.write('\n</svg>\n')
+    print("Wrote %d squares to %s" % (len(squares_and_labels), filename))
+
+
+def PrintNearMisses(working_words, word_trie):
+    """Print all the near misses (one bad column) for all the start words.
+    """
+
+    print("Generating near misses. These are NOT valid squares: the marked "
+          "column isn't a word.\n")
+    alphabet = WordAlphabet(working_words)
+    num_near_misses = 0
+    for word_num, start_word in enumerate(working_words):
+        for grid, bad_column in GenNearMisses(word_trie, start_word, alphabet):
+            print("NearMiss %d from word %d/%d: column %d (%s) is not a word" %
+                  (num_near_misses, word_num, len(working_words), bad_column,
+                   TransposeSquare(grid)[bad_column]))
+            for line in FormatSquareWithCoords(grid).split("\n"):
+                print("  " + line)
+            width = len(str(len(grid) - 1))
+            print("  " + " " * ((width + 1) * (bad_column + 1) + width - 1) + "^")
+            print("")
+            num_near_misses += 1
+
+    print("Made %d near misses" % num_near_misses)
+
+
+# Set in the environment of VerifyDeterministic's second run, to the file it
+# should write its squares to.
+VERIFY_OUTPUT_ENV = "SQUAREWORD_VERIFY_OUTPUT"
+
+
+def VerifyDeterministic(working_words, word_trie, config, argv,
+                        column_words=None, column_trie=None):
+    """Run the whole generation twice and make sure the output is identical.
+
+      This catches nondeterminism (e.g. from set iteration order) sneaking
+      into the search. The second run is a new process, started with argv
+      and a different PYTHONHASHSEED, so str hashes and so the order of sets
+      of words differ between the runs. Exits with an error if they make
+      different output.
+    """
+
+    output = []
+    squares = GenFilteredSquares(working_words, word_trie, config,
+                                 column_words, column_trie)
+    for sq_num, (word_num, sq, is_double_square,
+                 words_are_unique) in enumerate(squares):
+        output.append(FormatSquare(sq, sq_num, word_num, len(working_words),
+                                   is_double_square, words_are_unique))
+    if os.environ.get(VERIFY_OUTPUT_ENV):
+        # We're the second run, so just hand the squares back.
+        with open(os.environ[VERIFY_OUTPUT_ENV], "w") as f:
+            json.dump(output, f)
+        return
+    print("Run 0 made %d squares" % len(output))
+
+    # 0 turns hash randomization off, so it differs from any random seed.
+    hash_seed = os.environ.get("PYTHONHASHSEED", "random")
+    other_seed = str(int(hash_seed) + 1) if hash_seed.isdigit() else "0"
+    fd, output_file = tempfile.mkstemp(suffix=".json")
+    os.close(fd)
+    try:
+        env = dict(os.environ, PYTHONHASHSEED=other_seed)
+        env[VERIFY_OUTPUT_ENV] = output_file
+        subprocess.run([sys.executable, os.path.abspath(__file__)] + argv,
+                       env=env, stdout=subprocess.DEVNULL, check=True)
+        with open(output_file) as f:
+            other_output = json.load(f)
+    except (OSError, ValueError, subprocess.CalledProcessError) as e:
+        sys.exit("The second run failed: %s" % e)
+    finally:
+        os.remove(output_file)
+    print("Run 1, with PYTHONHASHSEED=%s, made %d squares" %
+          (other_seed, len(other_output)))
+
+    if output != other_output:
+        for sq_num, (first, second) in enumerate(zip(output, other_output)):
+            if first != second:
+                break
+        else:
+            sq_num = min(len(output), len(other_output))
+        sys.exit("Output is not deterministic: runs differ at square %d" %
+                 sq_num)
+
+    print("Both runs made identical output")
+
+
+class Checkpointer:
+    """Keeps a file saying how far a run has got, so it can be resumed.
+
+      The exhaustive search is deterministic (with a fixed --seed, if the
+      start words are shuffled), so all a resumed run needs is how many
+      start words it had got through and how many squares of the current
+      one were already printed. The file is JSON, rewritten at most once
+      every every_secs seconds, and replaced atomically so a kill can't
+      leave half a file.
+    """
+
+    def __init__(self, filename, every_secs, state=None):
+        """
+          Args:
+            filename: where to keep the checkpoint
+            every_secs: how often (at most) to rewrite it
+            state: Optional dict from Checkpointer.Load() to carry on from
+        """
+        self._filename = filename
+        self._every_secs = every_secs
+        self._last_write_time = time.time()
+        state = state or {}
+        self.position = state.get("position", 0)
+        self.squares_from_word = state.get("squares_from_word", 0)
+        self.sq_num = state.get("sq_num", 0)
+
+    @staticmethod
+    def Load(filename):
+        """Return the saved state, as a dict."""
+        with open(filename) as f:
+            return json.load(f)
+
+    def StartWord(self, position):
+        """Note the search has moved on to the start word at position."""
+        if position != self.position:
+            self.position = position
+            self.squares_from_word = 0
+        self._MaybeWrite()
+
+    def Square(self):
+        """Note we've printed another square."""
+        self.squares_from_word += 1
+        self.sq_num += 1
+        self._MaybeWrite()
+
+    def Finish(self, num_words):
+        """Note the run got to the end, so resuming it does nothing."""
+        self.position = num_words
+        self.squares_from_word = 0
+        self.Write()
+
+    def _MaybeWrite(self):
+        if time.time() - self._last_write_time >= self._every_secs:
+            self.Write()
+
+    def Write(self):
+        # Anything printed has to be out before the file says it was.
+        sys.stdout.flush()
+        tmp_filename = self._filename + ".tmp"
+        with open(tmp_filename, "w") as f:
+            json.dump({"position": self.position,
+                       "squares_from_word": self.squares_from_word,
+                       "sq_num": self.sq_num}, f)
+        os.replace(tmp_filename, self._filename)
+        self._last_write_time = time.time()
+
+
+class ProgressBar:
+    """Shows how far the search has got through the start words, on stderr.
+
+      The time left is a guess, from how long the start words so far took
+      on average.
+    """
+
+    def __init__(self, num_words, first_position=0, every_secs=0.5,
+                 width=30):
+        """
+          Args:
+            num_words: how many start words the search goes through
+            first_position: where the search starts, if it's resuming
+            every_secs: how often (at most) to redraw the bar
+            width: how many chars wide the bar itself is
+        """
+        self._num_words = num_words
+        self._first_position = first_position
+        self._every_secs = every_secs
+        self._width = width
+        self._start_time = time.time()
+        self._last_show_time = 0
+        self.position = first_position
+        self.squares = 0
+
+    def StartWord(self, position):
+        """Note the search has moved on to the start word at position."""
+        self.position = position
+        self._MaybeShow()
+
+    def Square(self):
+        """Note another square has come out."""
+        self.squares += 1
+        self._MaybeShow()
+
+    def Finish(self):
+        """Draw the bar one last time, and end its line."""
+        self._Show()
+        sys.stderr.write("\n")
+        sys.stderr.flush()
+
+    def _MaybeShow(self):
+        if time.time() - self._last_show_time >= self._every_secs:
+            self._Show()
+
+    def _Show(self):
+        done = self.position / self._num_words if self._num_words else 1
+        filled = int(done * self._width)
+        line = "[%s%s] %d/%d start words, %d squares" % (
+            "#" * filled, "." * (self._width - filled), self.position,
+            self._num_words, self.squares)
+        words_done = self.position - self._first_position
+        if 0 < words_done and self.position < self._num_words:
+            secs_left = ((time.time() - self._start_time) / words_done *
+                         (self._num_words - self.position))
+            line += ", about %d:%02d left" % divmod(int(secs_left), 60)
+        # Pad out to clear whatever was longer the last time.
+        sys.stderr.write("\r%-79s" % line)
+        sys.stderr.flush()
+        self._last_show_time = time.time()
+
+
+def PrintSearchStats(stats, num_squares, load_secs, search_secs, print_secs):
+    """Print what the search did, from the SEARCH_STATS counts, and how long
+      each part of the run took.
+
+      Only the row-by-row search (GenSubSquares) counts partial squares,
+      rows and dead ends, so they're 0 for the other searches.
+
+      Args:
+        stats: collections.Counter, SEARCH_STATS
+        num_squares: how many squares were made, after the filters
+        load_secs, search_secs, print_secs: time spent loading the word
+          lists, searching (and filtering), and printing squares
+    """
+
+    print("\nSearch stats:")
+    print("  %d partial squares expanded, trying %d row words" %
+          (stats["partial squares"], stats["rows tried"]))
+    print("  %d of them were dead ends, with no row word that fits below" %
+          stats["dead ends"])
+    print("  %d squares found, %d after the filters" %
+          (stats["squares before the filters"], num_squares))
+    print("  %.2f sec loading the word lists, %.2f sec searching, %.2f sec "
+          "printing" % (load_secs, search_secs, print_secs))
+
+
+def BlankCellOptions(normalize_opts):
+    """Return a copy of normalize_opts that also allows "." for a blank cell,
+      for normalizing grids and patterns.
+    """
+
+    return NormalizeOptions(
+        normalize_opts.alphabet and normalize_opts.alphabet | set("."),
+        normalize_opts.strip_accents)
+
+
+def ReadGrid(filename, normalize_opts=NormalizeOptions()):
+    """Read a partly filled in grid, one row per line, with "." for blanks.
+
+      Each row goes through NormalizeWord, like the words in the word files,
+      with "." allowed on top of normalize_opts.alphabet.
+
+      Returns:
+        list of row strings, all the same length
+      Raises:
+        ValueError if the rows aren't all the same length, or are empty, or
+        use chars outside the alphabet
+    """
+
+    grid_opts = BlankCellOptions(normalize_opts)
+    with open(filename) as f:
+        lines = [line for line in f if line.strip()]
+    grid = [NormalizeWord(line, grid_opts) for line in lines]
+    if None in grid:
+        raise ValueError("%s row %d uses chars outside the alphabet" %
+                         (filename, grid.index(None) + 1))
+    if not grid or len(set([len(row) for row in grid])) != 1:
+        raise ValueError("%s should have rows of the same length, one per "
+                         "line" % filename)
+    return grid
+
+
+def ReadBannedLetters(filename):
+    """Read a grid of banned letters, one row per line.
+
+      Each cell is a group of letters that can't go there, with the cells
+      separated by spaces and "." for a cell with no bans, like:
+        xz . . q
+        . . e .
+
+      Returns:
+        list per row of the set of banned letters in each cell
+      Raises:
+        ValueError if the rows don't all have the same number of cells, or
+        are empty
+    """
+
+    with open(filename) as f:
+        grid = [[set(cell) - set(".") for cell in line.lower().split()]
+                for line in f if line.strip()]
+    if not grid or len(set([len(row) for row in grid])) != 1:
+        raise ValueError("%s should have rows with the same number of cells, "
+                         "one row per line" % filename)
+    return grid
+
+
+def ReadSquaresFile(filename):
+    """Read the squares in a file, as a list of (line number, rows).
+
+      A square is either a block of lines, one row per line, with blank
+      lines between squares, or one line with its rows joined by "/" (like
+      --canonical_output). If the file has "WordSquare" lines, like our own
+      output, only the blocks after them count, and if it has "/" lines only
+      those count. Either way the word lists we print at the top are skipped.
+      Squares printed with --show_coords have their row and column numbers
+      taken off.
+    """
+
+    with open(filename) as f:
+        lines = [line.strip().lower() for line in f]
+    has_headers = any([line.startswith("wordsquare") for line in lines])
+    only_joined = any(["/" in line and " " not in line for line in lines])
+
+    squares = []
+    rows = []
+    start_line_num = 0
+    in_square = not has_headers and not only_joined
+    for line_num, line in enumerate(lines + [""], 1):
+        if in_square and line:
+            # The --show_coords layout: a line of column numbers, then each
+            # row's number followed by its letters, spaced out.
+            tokens = line.split()
+            if all([token.isdigit() for token in tokens]):
+                continue
+            if (len(tokens) > 1 and tokens[0].isdigit() and
+                    all([len(token) == 1 for token in tokens[1:]])):
+                line = "".join(tokens[1:])
+        if not line or " " in line or "/" in line:
+            if rows:
+                squares.append((start_line_num, rows))
+            rows = []
+            in_square = not only_joined and (
+                not has_headers or line.startswith("wordsquare"))
+            if "/" in line and " " not in line:
+                squares.append((line_num, line.split("/")))
+        elif in_square:
+            if not rows:
+                start_line_num = line_num
+            rows.append(line)
+    return squares
+
+
+def CheckSquare(sq, scrabble_set, top_words):
+    """Return a list of what's wrong with sq, empty if it's a valid square.
+
+      Args:
+        sq: list of row strings
+        scrabble_set: set of all the allowed words
+        top_words: function(word_len) that returns the set of the --top_n
+          words of that length
+    """
+
+    problems = ["row %d %s is %d letters, not %d" % (
+        i + 1, row, len(row), len(sq[0]))
+        for i, row in enumerate(sq) if len(row) != len(sq[0])]
+    if problems:
+        # The columns don't line up, so there's no point checking them.
+        return problems
+
+    lines = ([("row", i + 1, row) for i, row in enumerate(sq)] +
+             [("column", i + 1, column)
+              for i, column in enumerate(TransposeSquare(sq))])
+    for kind, num, word in lines:
+        if word not in scrabble_set:
+            problems.append("%s %d %s is not a word" % (kind, num, word))
+        elif word not in top_words(len(word)):
+            problems.append("%s %d %s is not in the top %d-letter words" %
+                            (kind, num, word, len(word)))
+    return problems
+
+
+def VerifySquaresFile(filename, freq_csv_file, scrabble_words_file, top_n,
+                      normalize_opts, input_format=None,
+                      exclude_words=frozenset(), ban_letters="",
+                      proper_nouns=frozenset(), freq_format=None):
+    """Check every square in a file against the word lists, and print why
+      any of them fail. Exits with an error if any do.
+    """
+
+    scrabble_set = WithoutLetters(
+        (ReadScrabbleSet(scrabble_words_file, normalize_opts, input_format) |
+         proper_nouns) - exclude_words, ban_letters)
+    top_words_by_len = {}
+
+    def TopWords(word_len):
+        if word_len not in top_words_by_len:
+            top_words_by_len[word_len] = set(PickTopWords(
+                freq_csv_file, scrabble_set, top_n, word_len, normalize_opts,
+                freq_format))
+        return top_words_by_len[word_len]
+
+    # Not normalize_opts itself: a row outside the alphabet should be
+    # reported as not a word, not dropped.
+    row_opts = NormalizeOptions(strip_accents=normalize_opts.strip_accents)
+    squares = ReadSquaresFile(filename)
+    num_valid = 0
+    for sq_num, (line_num, sq) in enumerate(squares):
+        sq = [NormalizeWord(row, row_opts) for row in sq]
+        problems = CheckSquare(sq, scrabble_set, TopWords)
+        if problems:
+            print("Square %d (line %d) %s: invalid" % (sq_num, line_num,
+                                                      "/".join(sq)))
+            for problem in problems:
+                print("  " + problem)
+        else:
+            num_valid += 1
+            print("Square %d (line %d) %s: valid" % (sq_num, line_num,
+                                                    "/".join(sq)))
+
+    print("%d of %d squares are valid, using the top %d words" %
+          (num_valid, len(squares), top_n))
+    if num_valid < len(squares):
+        sys.exit(1)
+
+
+def ParseRowPattern(text):
+    """Parse a --row_pattern value like "2:s...." into (1, "s....").
+
+      The pattern is normalized later, by main, once the alphabet is known.
+    """
+
+    row, _, pattern = text.partition(":")
+    try:
+        row = int(row)
+    except ValueError:
+        raise argparse.ArgumentTypeError("expected row:pattern, like 2:s....")
+    if row < 1:
+        raise argparse.ArgumentTypeError("rows are numbered from 1")
+    return row - 1, pattern
+
+
+def ParseShard(text):
+    """Parse a --shard value like "3/16" into (3, 16), for argparse."""
+
+    try:
+        i, n = [int(part) for part in text.split("/")]
+    except ValueError:
+        raise argparse.ArgumentTypeError("expected i/n, like 3/16")
+    if not 1 <= i <= n:
+        raise argparse.ArgumentTypeError("i has to be from 1 to n")
+    return i, n
+
+
+def ParseWordLens(text):
+    """Parse a --word_lens value like "4,5,6" into [4, 5, 6], for argparse."""
+
+    try:
+        word_lens = [int(part) for part in text.split(",")]
+    except ValueError:
+        raise argparse.ArgumentTypeError("expected lengths like 4,5,6")
+    if min(word_lens) < 1:
+        raise argparse.ArgumentTypeError("lengths have to be at least 1")
+    return word_lens
+
+
+def ConfigFromArgs(args, rng=None, theme_words=None):
+    """Make a Config from the parsed command line args.
+
+      Args:
+        theme_words: Optional set of words read from --theme_file
+    """
+
+    return Config(fill_order=args.fill_order,
+                  per_first_row_timeout_ms=args.per_first_row_timeout_ms,
+                  order_by_branching=args.order_by_branching,
+                  order_by_letter_frequency=args.order_by_letter_frequency,
+                  double_squares_only=args.double_squares_only,
+                  single_squares_only=args.single_squares_only,
+                  score_mode=args.score_mode,
+                  min_score=args.min_score,
+                  min_difficulty=args.min_difficulty,
+                  max_difficulty=args.max_difficulty,
+                  max_score=args.max_score,
+                  max_shared_prefix=args.max_shared_prefix,
+                  column_must_include=args.column_must_include.lower(),
+                  rare_letters=(args.rare_letters.lower()
+                                if args.require_rare_letter else ""),
+                  must_contain=args.must_contain,
+                  dedup_window=args.dedup_window,
+                  dedupe_transpose=(args.dedupe_transpose or
+                                    args.canonical_output),
+                  diagonal_word=args.diagonal_word,
+                  anti_diagonal_word=args.anti_diagonal_word,
+                  palindromes=args.palindromes,
+                  propagate=args.propagate,
+                  row_order=args.row_order,
+                  algorithm=args.algorithm,
+                  beam_width=args.beam_width,
+                  anneal_restarts=args.anneal_restarts,
+                  anneal_steps=args.anneal_steps,
+                  sample=args.sample,
+                  sample_walks=args.sample_walks,
+                  rng=rng,
+                  deepening_step=args.deepening_step,
+                  start_word=args.start_word,
+                  row_patterns=dict(args.row_pattern or []),
+                  banned_cells=args.banned_cells,
+                  min_distinct_letters=args.min_distinct_letters,
+                  max_letter_repeats=args.max_letter_repeats,
+                  max_total_rank=args.max_total_rank,
+                  theme_words=theme_words,
+                  min_theme_words=args.min_theme_words,
+                  shuffle=args.shuffle,
+                  shard=args.shard)
+
+
+def LoadWordLists(args, normalize_opts, exclude_words, lemmas, proper_nouns,
+                  separate_columns):
+    """Read the word lists for args.word_len and build their tries.
+
+      Args: as for MakeSquares
+      Returns:
+        (working_words, word_trie, column_words, column_trie). The column
+        ones are None unless separate_columns.
+    """
+
+    working_words = GetWorkingWords(args.freq_csv_file,
+                                    args.row_words or args.scrabble_words_file,
+                                    args.top_n, args.word_len, normalize_opts,
+                                    args.input_format, exclude_words,
+                                    args.ban_letters, args.min_vowels_per_row,
+                                    args.max_vowels_per_row, args.no_plurals,
+                                    lemmas, args.isogram_rows,
+                                    args.exclude_substring, proper_nouns,
+                                    args.top_n_per_letter, args.freq_format)
+
+    word_trie = WordTrie(working_words)
+
+    # For rectangles, the columns are words of a different length, so they
+    # get their own list and trie. So do columns from a different word file.
+    column_words = None
+    column_trie = None
+    if separate_columns:
+        column_top_n = args.top_n if args.col_top_n is None else args.col_top_n
+        column_words = GetWorkingWords(args.freq_csv_file,
+                                       args.col_words or args.scrabble_words_file,
+                                       column_top_n,
+                                       args.rows or args.word_len,
+                                       normalize_opts,
+                                       args.input_format, exclude_words,
+                                       args.ban_letters, args.min_vowels_per_row,
+                                       args.max_vowels_per_row,
+                                       args.no_plurals, lemmas,
+                                       args.isogram_rows,
+                                       args.exclude_substring, proper_nouns,
+                                       args.top_n_per_letter,
+                                       args.freq_format)
+        column_trie = WordTrie(column_words)
+
+    return working_words, word_trie, column_words, column_trie
+
+
+def WordListCacheFile(args, exclude_words, lemmas, proper_nouns,
+                      separate_columns):
+    """Return the --cache_dir file for LoadWordLists' result with these args.
+
+      The name is a hash of everything the result depends on: the contents
+      of the word files, the options that change which words get picked,
+      and this script itself, so a changed filter doesn't pick up stale
+      lists.
+    """
+
+    digest = hashlib.sha256()
+    word_files = [__file__, args.freq_csv_file,
+                  args.row_words or args.scrabble_words_file]
+    if separate_columns:
+        word_files.append(args.col_words or args.scrabble_words_file)
+    for filename in word_files:
+        try:
+            with open(filename, "rb") as f:
+                digest.update(hashlib.sha256(f.read()).digest())
+        except OSError as e:
+            raise WordFileError("Can't read %s: %s" % (filename, e))
+    digest.update(repr([
+        args.top_n, args.col_top_n, args.word_len, args.rows, args.alphabet,
+        args.strip_accents, args.input_format, args.freq_format,
+        args.ban_letters, args.min_vowels_per_row, args.max_vowels_per_row,
+        args.no_plurals, args.isogram_rows, args.exclude_substring,
+        args.top_n_per_letter, separate_columns, sorted(exclude_words),
+        sorted((lemmas or {}).items()), sorted(proper_nouns)]).encode())
+    return os.path.join(args.cache_dir,
+                        "words-%s.pickle" % digest.hexdigest()[:16])
+
+
+def MakeSquares(args, parser, normalize_opts, exclude_words, lemmas,
+                proper_nouns, separate_columns):
+    """Load the word lists for args.word_len, then make and print the squares,
+      or do whichever other mode args asks for.
+
+      Args:
+        args, parser: the parsed command line, and the parser, for errors
+        normalize_opts, exclude_words, lemmas, proper_nouns: read from the
+          files in args, by main
+        separate_columns: if True, the columns get their own word list
+      Returns:
+        (squares made, how many of them were double-word squares), or None
+        for the modes that don't make squares
+    """
+
+    global SEARCH_STATS
+    SEARCH_STATS = collections.Counter() if args.stats else None
+    load_start_time = time.time()
+
+    word_lists = None
+    if args.cache_dir:
+        cache_file = WordListCacheFile(args, exclude_words, lemmas,
+                                       proper_nouns, separate_columns)
+        if os.path.exists(cache_file):
+            with open(cache_file, "rb") as f:
+                word_lists = pickle.load(f)
+            print("Loaded the word lists from %s\n" % cache_file)
+    if word_lists is None:
+        word_lists = LoadWordLists(args, normalize_opts, exclude_words, lemmas,
+                                   proper_nouns, separate_columns)
+        if args.cache_dir:
+            os.makedirs(args.cache_dir, exist_ok=True)
+            # Per process, so two runs filling the cache at once don't mix.
+            tmp_filename = "%s.%d.tmp" % (cache_file, os.getpid())
+            with open(tmp_filename, "wb") as f:
+                pickle.dump(word_lists, f)
+            os.replace(tmp_filename, cache_file)
+    working_words, word_trie, column_words, column_trie = word_lists
+    if args.fill_order == "columns" and separate_columns:
+        # Filling a column at a time is the same search as a row at a time
+        # with the row and column words swapped, then transposed, which
+        # GenSquares does for fill_order "columns".
+        working_words, word_trie, column_words, column_trie = (
+            column_words, column_trie, working_words, word_trie)
+
+    if args.start_word is not None:
+        start_word_len = args.word_len
+        if args.fill_order == "columns":
+            start_word_len = args.rows or args.word_len
+        args.start_word = NormalizeWord(args.start_word, normalize_opts)
+        if args.start_word is None or len(args.start_word) != start_word_len:
+            parser.error("--start_word has to be a %d letter word, in the "
+                         "alphabet" % start_word_len)
+        if args.start_word not in working_words:
+            parser.error("--start_word %s isn't in the top %d words; try a "
+                         "bigger --top_n" % (args.start_word,
+                                             len(working_words)))
+
+    for row, pattern in args.row_pattern or []:
+        if row >= (args.rows or args.word_len) or len(pattern) != args.word_len:
+            parser.error("--row_pattern %d:%s doesn't fit a %d x %d grid" %
+                         (row + 1, pattern, args.rows or args.word_len,
+                          args.word_len))
+    if args.banned_cells and (
+            len(args.banned_cells) != (args.rows or args.word_len) or
+            len(args.banned_cells[0]) != args.word_len):
+        parser.error("--banned_letters_file is %d x %d, but the grid is "
+                     "%d x %d" % (len(args.banned_cells),
+                                  len(args.banned_cells[0]),
+                                  args.rows or args.word_len, args.word_len))
+
+    if args.dawg:
+        for trie in [word_trie, column_trie]:
+            if trie is not None:
+                trie_bytes = TrieBytes(trie)
+                trie.Minimize()
+                print("Merged the trie's shared endings: %.1f MB -> %.1f MB\n"
+                      % (trie_bytes / 1e6, TrieBytes(trie) / 1e6))
+    load_secs = time.time() - load_start_time
+    if args.show_memory:
+        PrintMemoryUsage(working_words, word_trie, column_words, column_trie)
+
+    if args.must_contain is not None:
+        args.must_contain = NormalizeWord(args.must_contain, normalize_opts)
+        if args.must_contain not in working_words + (column_words or []):
+            parser.error("--must_contain %s isn't one of the top %d words of "
+                         "the right length; try a bigger --top_n" %
+                         (args.must_contain, args.top_n))
+
+    # All randomness goes through this one generator, so a run is repeatable
+    # for a given --seed.
+    rng = random.Random(None if args.seed == -1 else args.seed)
+
+    if args.just_benchmark:
+        DoSomeBenchmarking(working_words, word_trie, rng,
+                           args.benchmark_secs)
+        return
+
+    if args.dry_run:
+        nodes, squares, seconds = EstimateSearch(working_words, word_trie, rng,
+                                                 args.dry_run_probes,
+                                                 column_trie)
+        trie_bytes = TrieBytes(word_trie)
+        if column_trie is not None:
+            trie_bytes += TrieBytes(column_trie)
+        print("From %d random probes, the search would visit about %.3g "
+              "partial squares and make about %.3g squares, taking about "
+              "%.0f sec." % (args.dry_run_probes, nodes, squares, seconds))
+        print("The tries take %.1f MB. The search only holds one partial "
+              "square at a time, but --dedupe_transpose, --sort_by score "
+              "and --canonical_output would remember about %.1f MB of squares." %
+              (trie_bytes / 1e6,
+               squares * sys.getsizeof(
+                   "/".join(working_words[:args.rows or args.word_len]))
+               / 1e6))
+        return
+
+    # Example valid 6x6 square:
+    #  market
+    #  avenue
+    #  relics
+    #  knight
+    #  euchre
+    #  rested
+
+    theme_words = None
+    if args.theme_file:
+        # --input_format is for the word files; this one's guessed from
+        # its name, like the other extra files.
+        theme_words = ReadScrabbleSet(args.theme_file, normalize_opts)
+        print("%d of the %d theme words are in the word lists\n" % (
+            len(theme_words & set(working_words + (column_words or []))),
+            len(theme_words)))
+
+    config = ConfigFromArgs(args, rng, theme_words)
+
+    if args.emit_near_misses:
+        PrintNearMisses(working_words, word_trie)
+        return
+
+    if args.cube:
+        PrintCubes(working_words, word_trie, args.limit, args.count_only)
+        return
+
+    if args.verify_deterministic:
+        VerifyDeterministic(working_words, word_trie, config, sys.argv[1:],
+                            column_words, column_trie)
+        return
+
+    # The start word the search is on, for the --max_seconds summary.
+    progress = {"position": 0}
+    checkpointer = None
+    skip_squares = 0
+    if args.checkpoint_file:
+        state = None
+        if args.resume and os.path.exists(args.checkpoint_file):
+            state = Checkpointer.Load(args.checkpoint_file)
+            config.first_position = state["position"]
+            skip_squares = state["squares_from_word"]
+            print("Resuming at start word %d, after its first %d squares\n" %
+                  (state["position"], skip_squares))
+        checkpointer = Checkpointer(args.checkpoint_file, args.checkpoint_secs,
+                                    state)
+    progress_bar = None
+    if args.progress:
+        progress_bar = ProgressBar(len(working_words), config.first_position)
+
+    def OnStartWord(position):
+        progress["position"] = position
+        if checkpointer:
+            checkpointer.StartWord(position)
+        if progress_bar:
+            progress_bar.StartWord(position)
+    config.on_start_word = OnStartWord
+    if args.max_seconds is not None:
+        config.deadline = time.time() + args.max_seconds
+
+    if args.shard:
+        # Recorded in the output, so sharded results can be merged later.
+        print("Shard %d/%d: start words %d, %d, %d, ... of %d\n" %
+              (args.shard[0], args.shard[1], args.shard[0] - 1,
+               args.shard[0] - 1 + args.shard[1],
+               args.shard[0] - 1 + 2 * args.shard[1], len(working_words)))
+
+    print("Generating Squares..\n")
+    if (args.dedupe_transpose or args.canonical_output) and not separate_columns:
+        print("Each square is printed once, as whichever of it and its "
+              "transpose comes first alphabetically\n")
+    sq_num = checkpointer.sq_num if checkpointer else 0
+    first_letter_counts = collections.Counter()
+    word_ranks = WordRanks(working_words, column_words or [])
+    contact_sheet_squares = []
+    double_square_count = 0
+    canonical_squares = []
+    scored_squares = []
+    # Only the proper nouns that aren't ordinary words too get tagged.
+    proper_noun_tags = frozenset()
+    if proper_nouns:
+        proper_noun_tags = proper_nouns - set().union(*[
+            ReadScrabbleSet(words_file, normalize_opts, args.input_format)
+            for words_file in set([args.row_words or args.scrabble_words_file,
+                                   args.col_words or
+                                   args.scrabble_words_file])])
+    difficulty_rater = None
+    if args.show_difficulty:
+        difficulty_rater = DifficultyRater(working_words, column_words or [])
+    squares_this_run = 0
+    hit_limit = False
+    search_start_time = time.time()
+    print_secs = 0
+    if args.threads != 1:
+        squares = GenParallelSquares(working_words, word_trie, config,
+                                     args.threads or os.cpu_count(),
+                                     column_words, column_trie)
+    else:
+        squares = GenFilteredSquares(working_words, word_trie, config,
+                                     column_words, column_trie)
+    for word_num, sq, is_double_square, words_are_unique in squares:
+        if skip_squares > 0 and progress["position"] == config.first_position:
+            # Printed before the run we're resuming was stopped.
+            skip_squares -= 1
+            continue
+        if args.canonical_output:
+            canonical_squares.append("/".join(sq))
+        elif args.sort_by == "score":
+            if not args.count_only:
+                scored_squares.append(
+                    (SquareScore(sq, word_ranks, args.score_mode), sq_num,
+                     word_num, sq, is_double_square, words_are_unique))
+        elif not args.count_only:
+            print_start_time = time.time()
+            score = None
+            if args.show_score:
+                score = SquareScore(sq, word_ranks, args.score_mode)
+            print(FormatSquare(sq, sq_num, word_num, len(working_words),
+                               is_double_square, words_are_unique,
+                               args.show_coords, score,
+                               difficulty_rater and difficulty_rater.Rate(sq),
+                               proper_noun_tags))
+            print_secs += time.time() - print_start_time
+        if is_double_square:
+            double_square_count += 1
+        first_letter_counts[sq[0][0]] += 1
+        if (args.contact_sheet and
+                len(contact_sheet_squares) < args.contact_sheet_count):
+            contact_sheet_squares.append(
+                (sq, FormatScore(SquareScore(sq, word_ranks, args.score_mode))))
+        sq_num += 1
+        squares_this_run += 1
+        if checkpointer:
+            checkpointer.Square()
+        if progress_bar:
+            progress_bar.Square()
+        if args.limit and squares_this_run >= args.limit:
+            hit_limit = True
+            break
+    # Unwinds the whole search, rather than leaving it suspended.
+    squares.close()
+    search_secs = time.time() - search_start_time - print_secs
+    if progress_bar:
+        if not hit_limit and not (config.deadline is not None and
+                                  time.time() > config.deadline):
+            progress_bar.StartWord(len(working_words))
+        progress_bar.Finish()
+
+    if args.canonical_output and not args.count_only:
+        for key in sorted(canonical_squares):
+            print(key)
+    if scored_squares:
+        # Low frequency scores are best, but high tile scores. Ties stay in
+        # search order.
+        sign = -1 if args.score_mode == "scrabble" else 1
+        scored_squares.sort(key=lambda s: (sign * s[0], s[1]))
+        for i, (score, _, word_num, sq, is_double_square,
+                words_are_unique) in enumerate(scored_squares):
+            print(FormatSquare(sq, i, word_num, len(working_words),
+                               is_double_square, words_are_unique,
+                               args.show_coords, score,
+                               difficulty_rater and difficulty_rater.Rate(sq),
+                               proper_noun_tags))
+    print("Made %d squares" % sq_num)
+    if args.count_only:
+        print("  %d single-word squares, %d double-word squares" %
+              (sq_num - double_square_count, double_square_count))
+    timed_out = config.deadline is not None and time.time() > config.deadline
+    if hit_limit:
+        print("Stopped at the --limit of %d squares" % args.limit)
+    elif timed_out:
+        print("Stopped after --max_seconds %g, partway through start word %d "
+              "of %d (%.1f%% of the list done)" %
+              (args.max_seconds, progress["position"], len(working_words),
+               100 * progress["position"] / len(working_words)))
+    if checkpointer:
+        if hit_limit or timed_out:
+            checkpointer.Write()
+        else:
+            checkpointer.Finish(len(working_words))
+
+    if args.contact_sheet:
+        WriteContactSheet(args.contact_sheet, contact_sheet_squares,
+                          args.contact_sheet_columns, args.contact_sheet_tile_size)
+
+    if args.count_by_first_letter:
+        PrintFirstLetterHistogram(first_letter_counts)
+
+    if SEARCH_STATS is not None:
+        PrintSearchStats(SEARCH_STATS, sq_num, load_secs, search_secs,
+                         print_secs)
+    if args.show_memory:
+        print("Peak RSS: %s" % FormatRss(PeakRssBytes()))
+
+    return sq_num, double_square_count
+
+
+def ReadFilesAndMakeSquares(args, parser, separate_columns):
+    """Read the files main's checked args name, then verify or make the
+      squares for each word length.
+
+      Args: as for MakeSquares
+    """
+
+    normalize_opts = NormalizeOptions(args.alphabet, args.strip_accents)
+    exclude_words = frozenset()
+    # --input_format and --freq_format only cover the word files, so the
+    # format of the other files is guessed from their names.
+    if args.exclude_file:
+        exclude_words = ReadScrabbleSet(args.exclude_file, normalize_opts)
+    if args.family_friendly:
+        exclude_words |= ReadScrabbleSet(DENY_WORDS_FILE, normalize_opts,
+                                         "text")
+    lemmas = None
+    if args.lemma_file:
+        lemmas = ReadLemmas(args.lemma_file, normalize_opts)
+    proper_nouns = frozenset()
+    if args.proper_nouns_file:
+        proper_nouns = ReadProperNouns(args.proper_nouns_file, normalize_opts)
+    if args.verify:
+        VerifySquaresFile(args.verify, args.freq_csv_file,
+                          args.scrabble_words_file, args.top_n, normalize_opts,
+                          args.input_format, exclude_words, args.ban_letters,
+                          proper_nouns, args.freq_format)
+        return
+
+    if args.word_lens:
+        results = []
+        for word_len in args.word_lens:
+            args.word_len = word_len
+            print("*** %d letter words ***\n" % word_len)
+            start_time = time.time()
+            result = MakeSquares(args, parser, normalize_opts, exclude_words,
+                                 lemmas, proper_nouns, separate_columns)
+            if result is not None:
+                results.append((word_len,) + result +
+                               (time.time() - start_time,))
+            print("")
+        if results:
+            print("Squares by word length:")
+            for word_len, num_squares, num_doubles, seconds in results:
+                print("  %2d letters: %d squares (%d single-word, %d "
+                      "double-word) in %.1f sec" %
+                      (word_len, num_squares, num_squares - num_doubles,
+                       num_doubles, seconds))
+        return
+
+    MakeSquares(args, parser, normalize_opts, exclude_words, lemmas,
+                proper_nouns, separate_columns)
+
+
+def main():
+    """ Main
+    """
+
+    parser = argparse.ArgumentParser()
+    # Inputs
+    parser.add_argument('--freq_csv_file', type=str,
+                        default='unigram_freq.csv',
+                        help="CSV file where the first row is words, "
+                        "sorted in most popular first")
+    parser.add_argument('--scrabble_words_file', type=str,
+                        default='scrabble_words.txt',
+                        help="Text file of valid words to use")
+
+    parser.add_argument('--row_words', type=str, default=None,
+                        help="Take the row words from this file instead of "
+                        "--scrabble_words_file")
+    parser.add_argument('--col_words', type=str, default=None,
+                        help="Take the column words from this file instead of "
+                        "--scrabble_words_file. They're still ranked by "
+                        "--freq_csv_file, and cut off at --col_top_n if it's "
+                        "given, or else --top_n")
+    parser.add_argument('--proper_nouns_file', type=str, default=None,
+                        help="File of proper nouns, like place names and "
+                        "given names, one per line, to allow as well as the "
+                        "scrabble words. They still have to be in "
+                        "--freq_csv_file and the --top_n. Squares list the "
+                        "ones they use")
+    parser.add_argument('--exclude_file', type=str, default=None,
+                        help="File of words, one per line, to never use in a "
+                        "row or column")
+    parser.add_argument('--ban_letters', type=str, default="",
+                        help="Don't use any word with these letters in it")
+    parser.add_argument('--min_vowels_per_row', type=int, default=0,
+                        help="Only use words with at least this many vowels "
+                        "(aeiou). The columns come from the same words, so "
+                        "this applies to them too")
+    parser.add_argument('--max_vowels_per_row', type=int, default=None,
+                        help="Only use words with at most this many vowels")
+    parser.add_argument('--exclude_substring', type=str, action="append",
+                        default=[],
+                        help="Don't use any word with this in it, like qu. "
+                        "Can be given more than once")
+    parser.add_argument('--isogram_rows', action="store_true",
+                        help="Only use words with no letter in them twice. "
+                        "Every column has to be one of the words too, so "
+                        "this applies to them as well")
+    parser.add_argument('--no_plurals', action="store_true",
+                        help="Leave out words that are just another word plus "
+                        "-s, -es or -ed, like cats, boxes and baked")
+    parser.add_argument('--lemma_file', type=str, default=None,
+                        help="File with a lemma then its forms on each line, "
+                        "like 'bake baking baked baker'. Forms whose lemma "
+                        "is a word are left out")
+    parser.add_argument('--family_friendly', action="store_true",
+                        help="Leave out the rude and offensive words in "
+                        "deny_words.txt, in rows and columns, so squares are "
+                        "safe to publish")
+    parser.add_argument('--input_format', choices=INPUT_FORMATS, default=None,
+                        help="How to parse the scrabble words file (and "
+                        "--row_words and --col_words). By default this is "
+                        "guessed from each file's extension")
+    parser.add_argument('--freq_format', choices=INPUT_FORMATS, default=None,
+                        help="How to parse the frequency file. By default "
+                        "this is guessed from its extension")
+    parser.add_argument('--alphabet', type=str, default=None,
+                        help="Only use words made of these chars, "
+                        "e.g. abcdefghijklmnopqrstuvwxyz")
+    parser.add_argument('--strip_accents', action="store_true",
+                        help="Turn accented chars into plain ones when "
+                        "reading words")
+
+    # Knobs
+    parser.add_argument('--top_n', type=int, default=None,
+                        help="Cutoff for N most popular words to use, for the "
+                        "rows and the columns. Defaults to 5000")
+    parser.add_argument('--col_top_n', type=int, default=None,
+                        help="Cutoff for the column words instead of --top_n. "
+                        "The columns get a word list of their own, as with "
+                        "--col_words")
+    parser.add_argument('--max_rank_per_word', type=int, default=None,
+                        help="The same cutoff as --top_n, by the rank each "
+                        "word can have. If both are given, the lower one "
+                        "wins")
+    parser.add_argument('--top_n_per_letter', action="store_true",
+                        help="Apply the --top_n cutoff per first letter, "
+                        "taking the top --top_n / 26 words starting with "
+                        "each letter, so common first letters like s, c and "
+                        "b don't crowd out the rest")
+    parser.add_argument('--word_len', type=int, default=5,
+                        help="Len of words to use")
+    parser.add_argument('--word_lens', type=ParseWordLens, default=None,
+                        help="Comma-separated lengths, like 4,5,6, to make "
+                        "squares of each size in turn, then sum up how many "
+                        "each made. Overrides --word_len")
+    parser.add_argument('--rows', type=int, default=None,
+                        help="With --cols, make rectangles of this many rows "
+                        "instead of squares. Overrides --word_len")
+    parser.add_argument('--cols', type=int, default=None,
+                        help="With --rows, the number of columns, i.e. the "
+                        "length of the row words")
+    parser.add_argument('--cube', action="store_true",
+                        help="Experimental: make NxNxN cubes where every row, "
+                        "column and pillar is a word, instead of squares. "
+                        "Only --limit and --count_only apply to them")
+    parser.add_argument('--deepening_step', type=int, default=0,
+                        help="Search the top N words, then the top 2N, and "
+                        "so on up to --top_n, so common-word squares come "
+                        "out first. Each square is printed once")
+    parser.add_argument('--max_word_len', type=int, default=15,
+                        help="Refuse --word_len values above this. The search "
+                        "space explodes with word length, so raise it only if "
+                        "you really want huge squares")
+    parser.add_argument('--cache_dir', type=str, default=None,
+                        help="Save the picked word lists and their tries in "
+                        "this directory, and load them from there next time "
+                        "the word files and options are the same, rather "
+                        "than reading the word files again")
+    parser.add_argument('--dawg', action="store_true",
+                        help="Merge the word tries' shared endings, so they "
+                        "take several times less memory. Same squares; "
+                        "doesn't work with --max_total_rank")
+    parser.add_argument('--fill_order', choices=["rows", "columns"],
+                        default="rows",
+                        help="Fill squares in a row at a time below each start "
+                        "word, or a column at a time beside it. With "
+                        "separate column words, the start words are column "
+                        "words then")
+    parser.add_argument('--per_first_row_timeout_ms', type=int, default=None,
+                        help="Give up on a start word's remaining squares "
+                        "after this many ms")
+    parser.add_argument('--algorithm',
+                        choices=["exhaustive", "beam", "anneal", "sat"],
+                        default="exhaustive",
+                        help="Find every square, use a beam search that "
+                        "quickly finds a few good ones, use simulated "
+                        "annealing from random grids, or find every square "
+                        "with a SAT solver (needs python-sat)")
+    parser.add_argument('--beam_width', type=int, default=100,
+                        help="How many partial squares the beam search keeps "
+                        "at each row")
+    parser.add_argument('--anneal_restarts', type=int, default=50,
+                        help="How many random grids annealing starts from")
+    parser.add_argument('--anneal_steps', type=int, default=100000,
+                        help="How many row swaps annealing tries per restart")
+    parser.add_argument('--sample', type=int, default=0,
+                        help="Instead of all the squares, print this many "
+                        "picked (roughly) uniformly at random")
+    parser.add_argument('--sample_walks', type=int, default=10000,
+                        help="How many random walks of the search tree "
+                        "--sample does. More gets closer to uniform")
+    parser.add_argument('--row_order', choices=["top_down", "most_constrained"],
+                        default="top_down",
+                        help="Fill rows top down, or fill whichever row has "
+                        "the fewest possible words next")
+    parser.add_argument('--propagate', action="store_true",
+                        help="Before searching each start word, narrow down "
+                        "the letters each cell can have (slower, but the "
+                        "same squares)")
+    parser.add_argument('--order_by_branching', action="store_true",
+                        help="Try letters that leave the most column words "
+                        "open first, to find squares sooner")
+    parser.add_argument('--order_by_letter_frequency', action="store_true",
+                        help="Try the letters used most in the word lists "
+                        "first, rather than alphabetically. Same squares, in "
+                        "a different order")
+    square_types = parser.add_mutually_exclusive_group()
+    square_types.add_argument('--double_squares_only', '--no_repeat_words',
+                              action="store_true",
+                              help="Print only valid double squares, where "
+                              "no word is used twice, as two rows or as a row "
+                              "and a column. Repeated rows are pruned as the "
+                              "search goes, the columns are checked at the "
+                              "end")
+    square_types.add_argument('--single_squares_only', '--symmetric_only',
+                              action="store_true",
+                              help="Print only single squares, where the "
+                              "columns are the same as the rows. This uses a "
+                              "much faster search")
+    parser.add_argument('--diagonal_word', action="store_true",
+                        help="The top left to bottom right diagonal has to be "
+                        "a word too")
+    parser.add_argument('--anti_diagonal_word', action="store_true",
+                        help="The top right to bottom left diagonal has to be "
+                        "a word too")
+    parser.add_argument('--palindromes', choices=["rows", "rotational"],
+                        default=None,
+                        help="Only make squares where every row is a "
+                        "palindrome, or that look the same turned 180 degrees")
+    parser.add_argument('--score_mode', choices=SCORE_MODES,
+                        default="frequency",
+                        help="How to score squares for --min_score, "
+                        "--max_score and --show_score: the mean frequency "
+                        "rank of the words (lower is more common), or the "
+                        "sum of the Scrabble tile values of the letters")
+    parser.add_argument('--min_score', type=float, default=None,
+                        help="Skip squares whose --score_mode score is below "
+                        "this")
+    parser.add_argument('--max_score', type=float, default=None,
+                        help="Skip squares whose --score_mode score is above "
+                        "this")
+    parser.add_argument('--min_difficulty', type=float, default=None,
+                        help="Skip squares that would be easier puzzles than "
+                        "this, from 0 to 100 (see --show_difficulty)")
+    parser.add_argument('--max_difficulty', type=float, default=None,
+                        help="Skip squares that would be harder puzzles than "
+                        "this, from 0 to 100")
+    parser.add_argument('--show_difficulty', action="store_true",
+                        help="Print how hard each square would be as a "
+                        "puzzle, from 0 to 100 and as easy, medium or hard, "
+                        "going by how rare its words and letter pairs are "
+                        "and how much its letters repeat")
+    parser.add_argument('--show_score', action="store_true",
+                        help="Print each square's --score_mode score after "
+                        "it's WordSquare line")
+    parser.add_argument('--max_total_rank', type=int, default=None,
+                        help="Skip squares whose row and column words' "
+                        "frequency ranks add up to more than this. Prunes "
+                        "the search as it goes")
+    parser.add_argument('--theme_file', type=str, default=None,
+                        help="File of theme words, one per line, for "
+                        "--min_theme_words")
+    parser.add_argument('--min_theme_words', type=int, default=1,
+                        help="With --theme_file, skip squares with fewer "
+                        "than this many theme words as rows or columns")
+    parser.add_argument('--max_shared_prefix', type=int, default=None,
+                        help="Skip squares where two rows start with the same "
+                        "letters for more than this many letters")
+    parser.add_argument('--column_must_include', '--require_letters',
+                        type=str, default="",
+                        help="Only print squares that use all of these "
+                        "letters. Branches that can't fit them any more are "
+                        "pruned")
+    parser.add_argument('--must_contain', type=str, default=None,
+                        help="Only print squares with this word as one of the "
+                        "rows or columns. Branches that can't fit it any more "
+                        "are pruned")
+    parser.add_argument('--require_rare_letter', action="store_true",
+                        help="Only print squares that use at least one of "
+                        "--rare_letters. Branches that can't any more are "
+                        "pruned")
+    parser.add_argument('--rare_letters', type=str, default="jqxz",
+                        help="The letters for --require_rare_letter")
+    parser.add_argument('--min_distinct_letters', type=int, default=0,
+                        help="Only print squares that use at least this many "
+                        "different letters. Prunes the search near the end")
+    parser.add_argument('--max_letter_repeats', type=int, default=0,
+                        help="Only print squares that use no letter more "
+                        "than this many times. 1 means every cell is a "
+                        "different letter. Prunes as rows are placed")
+    parser.add_argument('--dedup_window', type=int, default=0,
+                        help="Skip squares (or their transposes) seen within "
+                        "the last N squares. Uses bounded memory, but misses "
+                        "duplicates further apart than N")
+    parser.add_argument('--dedupe_transpose', action="store_true",
+                        help="Print each double square once, not once each "
+                        "way round. Remembers every square, so it uses more "
+                        "memory than --dedup_window")
+
+    # Output
+    parser.add_argument('--contact_sheet', type=str, default=None,
+                        help="SVG file to draw the first squares into, as a "
+                        "grid of tiles")
+    parser.add_argument('--contact_sheet_count', type=int, default=24,
+                        help="How many squares to draw in the contact sheet")
+    parser.add_argument('--contact_sheet_columns', type=int, default=6,
+                        help="Tiles per row in the contact sheet")
+    parser.add_argument('--contact_sheet_tile_size', type=int, default=120,
+                        help="Size of each square in the contact sheet, in "
+                        "pixels")
+
+    parser.add_argument('--seed', type=int, default=123,
+                        help="Seed for anything random. Use -1 to seed from "
+                        "the current time")
+
+    # Debugging options
+    parser.add_argument('--log_details', action="store_true")
+    parser.add_argument('--count_by_first_letter', action="store_true",
+                        help="At the end, print how many squares started "
+                        "with each letter")
+    parser.add_argument('--show_coords', action="store_true",
+                        help="Print squares with row/column numbers")
+    parser.add_argument('--canonical_output', action="store_true",
+                        help="Print each square on one line, rows joined by "
+                        "'/', in its --dedupe_transpose form, sorted once "
+                        "the search is done. Two runs that find the same "
+                        "squares print the same thing, whatever order they "
+                        "searched in")
+    parser.add_argument('--sort_by', choices=["search", "score"],
+                        default="search",
+                        help="Print squares in the order the search finds "
+                        "them, or hold them all until it's done and print "
+                        "them best --score_mode score first (most common "
+                        "words, or most Scrabble points), with their scores")
+    parser.add_argument('--count_only', action="store_true",
+                        help="Don't print the squares, just count them, split "
+                        "into single and double squares")
+    parser.add_argument('--limit', type=int, default=0,
+                        help="Stop the search as soon as this many squares "
+                        "have been made")
+    parser.add_argument('--start_word', type=str, default=None,
+                        help="Only make squares with this word as the top "
+                        "row (or the left column, with --fill_order "
+                        "columns). It has to be one of the --top_n words")
+    parser.add_argument('--row_pattern', type=ParseRowPattern,
+                        action="append",
+                        help="row:pattern, like 2:s...e, to make row 2 (from "
+                        "1 at the top) start with s and end with e. '.' is "
+                        "any letter. Can be given more than once")
+    parser.add_argument('--banned_letters_file', type=str, default=None,
+                        help="File with a grid of letters each cell can't "
+                        "have, a row per line and cells separated by spaces, "
+                        "like 'xz . . q', with '.' for no bans")
+    parser.add_argument('--shuffle', action="store_true",
+                        help="Go through the start words in a random order "
+                        "(repeatable with --seed) rather than most popular "
+                        "first, so an early stop isn't biased to the top "
+                        "words")
+    parser.add_argument('--max_seconds', type=float, default=None,
+                        help="Stop the search after this long, and say how "
+                        "far through the start words it got")
+    parser.add_argument('--stats', action="store_true",
+                        help="At the end, say how many partial squares the "
+                        "search went through, how many were dead ends, and "
+                        "how long loading, searching and printing took")
+    parser.add_argument('--show_memory', action="store_true",
+                        help="Say how much memory the tries and word lists "
+                        "take once they're loaded, and the peak RSS then and "
+                        "at the end")
+    parser.add_argument('--progress', action="store_true",
+                        help="Show a progress bar on stderr, with how many "
+                        "start words are done and a guess at the time left")
+    parser.add_argument('--threads', type=int, default=1,
+                        help="Search with this many processes at once, 0 for "
+                        "one per core. The squares come out in the same "
+                        "order as with one")
+    parser.add_argument('--shard', type=ParseShard, default=None,
+                        help="i/n: only search every n'th start word, "
+                        "starting from the i'th, so n runs with i from 1 to "
+                        "n split the search between them")
+    parser.add_argument('--checkpoint_file', type=str, default=None,
+                        help="Keep a note of how far the search has got in "
+                        "this file, for --resume")
+    parser.add_argument('--checkpoint_secs', type=float, default=60,
+                        help="How often to update --checkpoint_file")
+    parser.add_argument('--resume', action="store_true",
+                        help="Carry on from where the run that wrote "
+                        "--checkpoint_file stopped, with the same options. "
+                        "Square numbers carry on, but the histogram and "
+                        "contact sheet only cover the resumed part")
+    parser.add_argument('--just_benchmark', action="store_true",
+                        help="Don't make squares, just time building the "
+                        "trie, making words and each search")
+    parser.add_argument('--benchmark_secs', type=float, default=10,
+                        help="How long --just_benchmark runs each search for")
+    parser.add_argument('--dry_run', action="store_true",
+                        help="Don't search, just estimate how long the plain "
+                        "search for these words would take, by walking "
+                        "random paths through it. Ignores the filters and "
+                        "other search options")
+    parser.add_argument('--dry_run_probes', type=int, default=2000,
+                        help="How many random paths --dry_run walks")
+    parser.add_argument('--emit_near_misses', action="store_true",
+                        help="Instead of squares, print (invalid!) grids where "
+                        "every row and all but one column are words")
+    parser.add_argument('--verify_deterministic', action="store_true",
+                        help="Generate everything twice, the second time in "
+                        "a new process with a different PYTHONHASHSEED, and "
+                        "check that both runs make identical output")
+    parser.add_argument('--verify', type=str, default=None,
+                        help="Instead of making squares, check the ones in "
+                        "this file, and say which rows and columns aren't "
+                        "--top_n words. Takes our output, --canonical_output, "
+                        "or squares one row per line with blank lines "
+                        "between")
+    parser.add_argument('--solve', type=str, default=None,
+                        help="Read a partly filled in grid from this file, a "
+                        "row per line with '.' for blank cells, and print "
+                        "every way to fill it in from the --top_n words. "
+                        "Sets the grid size")
+
+    args = parser.parse_args()
+    # Both of these cap each word's rank, so the tighter one is the cutoff.
+    rank_cutoffs = [cutoff for cutoff in [args.top_n, args.max_rank_per_word]
+                    if cutoff is not None]
+    args.top_n = min(rank_cutoffs) if rank_cutoffs else 5000
+    if args.col_top_n is not None and args.max_rank_per_word is not None:
+        args.col_top_n = min(args.col_top_n, args.max_rank_per_word)
+    if args.solve:
+        if args.row_pattern or args.rows or args.cols:
+            parser.error("--solve sets the grid, so it doesn't work with "
+                         "--row_pattern, --rows or --cols")
+        try:
+            grid = ReadGrid(args.solve, NormalizeOptions(args.alphabet,
+                                                         args.strip_accents))
+        except (OSError, ValueError) as e:
+            parser.error("Can't read --solve grid: %s" % e)
+        args.rows, args.cols = len(grid), len(grid[0])
+        args.row_pattern = list(enumerate(grid))
+    elif args.row_pattern:
+        # Like the words, so a pattern matches however the words were
+        # spelled in their files.
+        pattern_opts = BlankCellOptions(NormalizeOptions(args.alphabet,
+                                                         args.strip_accents))
+        row_patterns = []
+        for row, pattern in args.row_pattern:
+            normalized = NormalizeWord(pattern, pattern_opts)
+            if normalized is None:
+                parser.error("--row_pattern %d:%s uses chars outside the "
+                             "alphabet" % (row + 1, pattern))
+            row_patterns.append((row, normalized))
+        args.row_pattern = row_patterns
+    if (args.rows is None) != (args.cols is None):
+        parser.error("--rows and --cols have to be used together")
+    making_rectangles = args.rows is not None and args.rows != args.cols
+    separate_columns = (making_rectangles or args.row_words is not None or
+                        args.col_words is not None or
+                        args.col_top_n is not None)
+    if separate_columns and (args.single_squares_only or
+                             args.emit_near_misses or args.diagonal_word or
+                             args.anti_diagonal_word or args.palindromes or
+                             args.cube):
+        parser.error("Rectangles, --row_words, --col_words and --col_top_n "
+                     "don't work with --single_squares_only, "
+                     "--emit_near_misses, --palindromes, --cube or the "
+                     "diagonal options")
+    if args.cube:
+        # The cube search has none of the square search's filters or output
+        # options, so rather than quietly ignore them, say so.
+        cube_unsupported = [flag for flag, used in [
+            ("--fill_order", args.fill_order != "rows"),
+            ("--row_order", args.row_order != "top_down"),
+            ("--algorithm", args.algorithm != "exhaustive"),
+            ("--sample", args.sample),
+            ("--deepening_step", args.deepening_step),
+            ("--shuffle", args.shuffle),
+            ("--shard", args.shard),
+            ("--threads", args.threads != 1),
+            ("--start_word", args.start_word),
+            ("--must_contain", args.must_contain),
+            ("--row_pattern/--solve", args.row_pattern),
+            ("--banned_letters_file", args.banned_letters_file),
+            ("--double_squares_only", args.double_squares_only),
+            ("--single_squares_only", args.single_squares_only),
+            ("--min_score/--max_score",
+             args.min_score is not None or args.max_score is not None),
+            ("--min_difficulty/--max_difficulty",
+             args.min_difficulty is not None or
+             args.max_difficulty is not None),
+            ("--max_shared_prefix", args.max_shared_prefix is not None),
+            ("--column_must_include", args.column_must_include),
+            ("--require_rare_letter", args.require_rare_letter),
+            ("--dedup_window", args.dedup_window),
+            ("--dedupe_transpose/--canonical_output",
+             args.dedupe_transpose or args.canonical_output),
+            ("--diagonal_word/--anti_diagonal_word",
+             args.diagonal_word or args.anti_diagonal_word),
+            ("--palindromes", args.palindromes),
+            ("--min_distinct_letters", args.min_distinct_letters),
+            ("--max_letter_repeats", args.max_letter_repeats),
+            ("--max_total_rank", args.max_total_rank is not None),
+            ("--theme_file", args.theme_file),
+            ("--max_seconds", args.max_seconds is not None),
+            ("--per_first_row_timeout_ms",
+             args.per_first_row_timeout_ms is not None),
+            ("--checkpoint_file", args.checkpoint_file),
+            ("--sort_by", args.sort_by != "search"),
+            ("--show_score", args.show_score),
+            ("--show_difficulty", args.show_difficulty),
+            ("--show_coords", args.show_coords),
+            ("--contact_sheet", args.contact_sheet),
+            ("--count_by_first_letter", args.count_by_first_letter),
+            ("--progress", args.progress),
+            ("--stats", args.stats),
+            ("--emit_near_misses", args.emit_near_misses),
+            ("--verify_deterministic", args.verify_deterministic)] if used]
+        if cube_unsupported:
+            parser.error("--cube doesn't work with %s. Of the search "
+                         "options, it only takes --limit and --count_only" %
+                         ", ".join(cube_unsupported))
+    if args.palindromes == "rows" and (args.fill_order == "columns" or
+                                       args.single_squares_only):
+        parser.error("--palindromes rows doesn't work with --fill_order "
+                     "columns or --single_squares_only")
+    if args.row_order == "most_constrained" and (
+            args.single_squares_only or args.diagonal_word or
+            args.anti_diagonal_word or args.palindromes or args.propagate):
+        parser.error("--row_order most_constrained doesn't work with "
+                     "--single_squares_only, --palindromes, --propagate or "
+                     "the diagonal options")
+    if args.algorithm == "beam" and (args.single_squares_only or
+                                     args.row_order != "top_down" or
+                                     args.propagate):
+        parser.error("--algorithm beam doesn't work with --single_squares_only, "
+                     "--row_order or --propagate")
+    if args.sample and (args.algorithm != "exhaustive" or
+                        args.single_squares_only or args.row_order != "top_down"
+                        or args.propagate):
+        parser.error("--sample doesn't work with --algorithm, "
+                     "--single_squares_only, --row_order or --propagate")
+    if args.algorithm == "sat":
+        if importlib.util.find_spec("pysat") is None:
+            parser.error("--algorithm sat needs the python-sat package: "
+                         "pip install python-sat")
+        if (args.single_squares_only or args.row_order != "top_down" or
+                args.propagate or args.diagonal_word or
+                args.anti_diagonal_word or args.palindromes):
+            parser.error("--algorithm sat doesn't work with "
+                         "--single_squares_only, --row_order, --propagate, "
+                         "--palindromes or the diagonal options")
+    if args.algorithm == "anneal" and (
+            args.single_squares_only or args.row_order != "top_down" or
+            args.propagate or args.diagonal_word or args.anti_diagonal_word or
+            args.palindromes == "rotational"):
+        parser.error("--algorithm anneal doesn't work with "
+                     "--single_squares_only, --row_order, --propagate, the "
+                     "diagonal options or --palindromes rotational")
+    if args.start_word and (args.algorithm != "exhaustive" or args.sample):
+        parser.error("--start_word only works with the exhaustive search")
+    if args.row_pattern and (args.fill_order == "columns" or
+                             args.row_order != "top_down" or
+                             args.algorithm in ["anneal", "sat"]):
+        parser.error("--row_pattern doesn't work with --fill_order columns, "
+                     "--row_order or --algorithm anneal or sat")
+    args.banned_cells = None
+    if args.banned_letters_file:
+        if (args.fill_order == "columns" or args.row_order != "top_down" or
+                args.algorithm in ["anneal", "sat"]):
+            parser.error("--banned_letters_file doesn't work with "
+                         "--fill_order columns, --row_order or --algorithm "
+                         "anneal or sat")
+        try:
+            args.banned_cells = ReadBannedLetters(args.banned_letters_file)
+        except (OSError, ValueError) as e:
+            parser.error("Can't read --banned_letters_file: %s" % e)
+    if args.shuffle and (args.algorithm != "exhaustive" or args.sample or
+                         args.deepening_step):
+        parser.error("--shuffle only works with the exhaustive search, and "
+                     "not with --sample or --deepening_step")
+    if args.shard and (args.algorithm != "exhaustive" or args.sample):
+        parser.error("--shard only works with the exhaustive search")
+    if args.threads < 0:
+        parser.error("--threads has to be 0 (one per core) or more")
+    if args.threads != 1 and (
+            args.algorithm != "exhaustive" or args.sample or
+            args.deepening_step or args.shard or args.checkpoint_file or
+            args.max_seconds is not None or args.dedup_window or
+            args.dedupe_transpose or args.canonical_output or
+            args.verify_deterministic or args.stats):
+        parser.error("--threads only works with the exhaustive search, and "
+                     "not with --sample, --deepening_step, --shard, "
+                     "--checkpoint_file, --max_seconds, --stats, "
+                     "--verify_deterministic or the dedupe options")
+    if args.verify_deterministic and (
+            args.word_lens or "-" in [args.freq_csv_file,
+                                      args.scrabble_words_file,
+                                      args.row_words, args.col_words]):
+        parser.error("--verify_deterministic reruns the command for the "
+                     "second run, so it doesn't work with --word_lens or "
+                     "word files read from stdin")
+    if args.max_seconds is not None and (args.algorithm != "exhaustive" or
+                                         args.sample or args.deepening_step):
+        parser.error("--max_seconds only works with the exhaustive search, "
+                     "and not with --sample or --deepening_step")
+    if args.progress and (args.algorithm != "exhaustive" or args.sample or
+                          args.deepening_step):
+        parser.error("--progress only works with the exhaustive search, "
+                     "and not with --sample or --deepening_step")
+    if args.resume and not args.checkpoint_file:
+        parser.error("--resume needs --checkpoint_file")
+    if args.checkpoint_file and (
+            args.algorithm != "exhaustive" or args.sample or
+            args.deepening_step or args.dedup_window or
+            args.dedupe_transpose or args.canonical_output or
+            args.sort_by == "score"):
+        parser.error("--checkpoint_file only works with the exhaustive "
+                     "search, and not with --sample, --deepening_step, "
+                     "--sort_by score or the dedupe options")
+    if args.sort_by == "score" and args.canonical_output:
+        parser.error("--sort_by score and --canonical_output are different "
+                     "orders; pick one")
+    if args.checkpoint_file and args.shuffle and args.seed == -1:
+        parser.error("--checkpoint_file with --shuffle needs a fixed --seed, "
+                     "so a resumed run shuffles the same way")
+    if args.cache_dir and "-" in [args.freq_csv_file, args.scrabble_words_file,
+                                  args.row_words, args.col_words]:
+        parser.error("--cache_dir can't tell if stdin has changed, so it "
+                     "only works with word files")
+    if args.dawg and args.max_total_rank is not None:
+        parser.error("--max_total_rank needs each trie node's own path, so "
+                     "it doesn't work with --dawg")
+    if args.order_by_branching and args.order_by_letter_frequency:
+        parser.error("Pick one of --order_by_branching and "
+                     "--order_by_letter_frequency")
+    if args.anti_diagonal_word and args.fill_order == "columns":
+        # Transposing the square would reverse the anti-diagonal.
+        parser.error("--anti_diagonal_word only works with --fill_order rows")
+    if args.word_lens and (args.rows or args.cols or args.start_word or
+                           args.must_contain or args.row_pattern or
+                           args.banned_letters_file or args.checkpoint_file):
+        parser.error("--word_lens doesn't work with the options for one "
+                     "grid size: --rows, --cols, --solve, --start_word, "
+                     "--must_contain, --row_pattern, --banned_letters_file "
+                     "or --checkpoint_file")
+    if args.cols is not None:
+        args.word_len = args.cols
+    for word_len in (args.word_lens or [args.word_len]) + [args.rows or 0]:
+        if word_len > args.max_word_len:
+            parser.error("Word length %d is more than --max_word_len %d. The "
+                         "number of partial squares to search grows "
+                         "explosively with the word length; raise "
+                         "--max_word_len to try anyway" %
+                         (word_len, args.max_word_len))
+    global LOG_DETAILS
+    LOG_DETAILS = args.log_details
+    args.ban_letters = args.ban_letters.lower()
+    args.exclude_substring = [substring.lower()
+                              for substring in args.exclude_substring]
 
+    try:
+        ReadFilesAndMakeSquares(args, parser, separate_columns)
+    except WordFileError as e:
+        parser.error(str(e))
 
 if __name__ == "__main__":
     main()
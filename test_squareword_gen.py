@@ -0,0 +1,65 @@
+"""Tests for squareword_gen.py. Run with: python3 -m unittest
+"""
+
+import collections
+import random
+import unittest
+
+import squareword_gen
+
+
+# The 30 most common 3 letter words, which make 6 squares.
+SMALL_WORD_LIST = [
+    "the", "and", "for", "you", "not", "are", "all", "new", "was", "can",
+    "has", "but", "our", "one", "may", "out", "use", "any", "see", "his",
+    "who", "web", "now", "get", "how", "its", "top", "had", "day", "two"]
+
+
+class GenSampledSquaresTest(unittest.TestCase):
+
+    def testSamplesAreCloseToUniform(self):
+        trie = squareword_gen.WordTrie(SMALL_WORD_LIST)
+        all_squares = set([tuple(sq) for word in SMALL_WORD_LIST
+                           for sq in squareword_gen.GenSquares(trie, word)])
+        self.assertEqual(len(all_squares), 6)
+
+        # Some of the squares are several times likelier than others to come
+        # out of a random walk. If the weights don't make up for that, those
+        # get picked far more than 1/6 of the time.
+        rng = random.Random(1)
+        num_draws = 2000
+        counts = collections.Counter()
+        for _ in range(num_draws):
+            samples = squareword_gen.GenSampledSquares(
+                trie, trie, SMALL_WORD_LIST, rng, 1, 100)
+            counts[tuple(samples[0])] += 1
+
+        self.assertEqual(set(counts), all_squares)
+        expected = num_draws / len(all_squares)
+        for sq, count in counts.items():
+            self.assertLess(abs(count - expected), 0.25 * expected,
+                            "%s was picked %d times" % ("/".join(sq), count))
+
+
+class MinDistinctLettersCellFilterTest(unittest.TestCase):
+
+    def testKeepsExactlyTheSquaresWithEnoughLetters(self):
+        trie = squareword_gen.WordTrie(SMALL_WORD_LIST)
+        for min_distinct in range(4, 10):
+            cell_filter = squareword_gen.MinDistinctLettersCellFilter(
+                trie, min_distinct)
+            expected = set()
+            found = set()
+            for word in SMALL_WORD_LIST:
+                for sq in squareword_gen.GenSquares(trie, word):
+                    if len(set("".join(sq))) >= min_distinct:
+                        expected.add(tuple(sq))
+                for sq in squareword_gen.GenSquares(trie, word,
+                                                    cell_filter=cell_filter):
+                    if len(set("".join(sq))) >= min_distinct:
+                        found.add(tuple(sq))
+            self.assertEqual(found, expected, "min_distinct=%d" % min_distinct)
+
+
+if __name__ == "__main__":
+    unittest.main()